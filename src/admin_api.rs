@@ -0,0 +1,226 @@
+//! JSON endpoints to look into a running world without a game client.
+//! Reads are open, mutations need the `X-Admin-Token` header to match
+//! the `LUNACIA_ADMIN_TOKEN` environment variable and are refused when it is not set.
+//! The `world` query parameter picks the world, the default one otherwise.
+
+use actix::prelude::*;
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::commands::{AdminCommand, CommandError, CommandRegistry, Dispatch};
+use crate::lunacia_world::{LunaciaWorldActor, InspectWorld, RenderMetrics, WorldQuery};
+use crate::world_manager::{self, GetWorld, ListWorlds, RouteCommand, WorldManager, DEFAULT_WORLD};
+
+const ADMIN_TOKEN_ENV: &str = "LUNACIA_ADMIN_TOKEN";
+const ADMIN_TOKEN_HEADER: &str = "X-Admin-Token";
+
+pub type ManagerData = web::Data<Addr<WorldManager>>;
+
+#[derive(Deserialize)]
+pub struct WorldSelector {
+    world: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct TeleportQuery {
+    x: i32,
+    y: i32,
+}
+
+#[derive(Deserialize)]
+pub struct TileQuery {
+    kind: String,
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.route("/metrics", web::get().to(metrics));
+    cfg.service(
+        web::scope("/api")
+            .route("/worlds", web::get().to(worlds))
+            .route("/stats", web::get().to(stats))
+            .route("/entities/{id}", web::get().to(entity))
+            .route("/entities/{id}", web::delete().to(despawn))
+            .route("/entities/{id}/teleport", web::post().to(teleport))
+            .route("/chunks/{x}/{y}", web::get().to(chunk))
+            .route("/paths/stats", web::get().to(path_stats))
+            .route("/tiles/{x}/{y}", web::get().to(tile))
+            .route("/tiles/{x}/{y}", web::put().to(set_tile))
+            .route("/parcels/{x}/{y}", web::get().to(parcel))
+            .route("/owners/{id}/parcels", web::get().to(owner_parcels))
+            .route("/buildings/{id}", web::get().to(building))
+            .route("/owners/{id}/buildings", web::get().to(owner_buildings))
+            .route("/commands", web::post().to(command))
+    );
+}
+
+fn error_response(e: &CommandError) -> HttpResponse {
+    let body = json!({ "error": e.to_string() });
+    match e {
+        CommandError::NotFound(_) => HttpResponse::NotFound().json(body),
+        CommandError::Forbidden(_) => HttpResponse::Forbidden().json(body),
+        CommandError::Failed(_) => HttpResponse::InternalServerError().json(body),
+        _ => HttpResponse::BadRequest().json(body),
+    }
+}
+
+fn unavailable() -> HttpResponse {
+    HttpResponse::ServiceUnavailable().json(json!({ "error": "world unavailable" }))
+}
+
+/// The request carries the admin token, WebSocket handshakes included
+pub fn authorized(req: &HttpRequest) -> bool {
+    let expected = match std::env::var(ADMIN_TOKEN_ENV) {
+        Ok(token) if !token.is_empty() => token,
+        _ => return false,
+    };
+    match req.headers().get(ADMIN_TOKEN_HEADER).and_then(|v| v.to_str().ok()) {
+        Some(token) => token == expected,
+        None => false,
+    }
+}
+
+fn selected_world(req: &HttpRequest) -> Option<String> {
+    web::Query::<WorldSelector>::from_query(req.query_string())
+        .ok()
+        .and_then(|q| q.into_inner().world)
+}
+
+fn forbidden() -> HttpResponse {
+    HttpResponse::Forbidden().json(json!({ "error": "admin token required" }))
+}
+
+async fn world_for(manager: &ManagerData, req: &HttpRequest) -> Result<Addr<LunaciaWorldActor>, HttpResponse> {
+    let name = selected_world(req);
+    match manager.send(GetWorld{name: name.clone()}).await {
+        Ok(Some(world)) => Ok(world),
+        Ok(None) => {
+            let name = name.unwrap_or_else(|| DEFAULT_WORLD.to_string());
+            Err(HttpResponse::NotFound().json(json!({ "error": format!("no world {}", name) })))
+        },
+        Err(_) => Err(unavailable()),
+    }
+}
+
+async fn query_world(manager: &ManagerData, req: &HttpRequest, query: WorldQuery) -> HttpResponse {
+    let world = match world_for(manager, req).await {
+        Ok(world) => world,
+        Err(response) => return response,
+    };
+    match world.send(InspectWorld(query)).await {
+        Ok(Ok(value)) => HttpResponse::Ok().json(value),
+        Ok(Err(e)) => error_response(&e),
+        Err(_) => unavailable(),
+    }
+}
+
+async fn run_command(manager: &ManagerData, world: Option<String>, cmd: AdminCommand) -> HttpResponse {
+    match manager.send(RouteCommand{world, cmd}).await {
+        Ok(Ok(text)) => HttpResponse::Ok().json(json!({ "result": text })),
+        Ok(Err(e)) => error_response(&e),
+        Err(_) => unavailable(),
+    }
+}
+
+async fn worlds(manager: ManagerData) -> HttpResponse {
+    match manager.send(ListWorlds).await {
+        Ok(names) => HttpResponse::Ok().json(names),
+        Err(_) => unavailable(),
+    }
+}
+
+async fn metrics(req: HttpRequest, manager: ManagerData) -> HttpResponse {
+    let world = match world_for(&manager, &req).await {
+        Ok(world) => world,
+        Err(response) => return response,
+    };
+    match world.send(RenderMetrics{prometheus: true}).await {
+        Ok(text) => HttpResponse::Ok().content_type("text/plain; version=0.0.4").body(text),
+        Err(_) => unavailable(),
+    }
+}
+
+async fn stats(req: HttpRequest, manager: ManagerData) -> HttpResponse {
+    query_world(&manager, &req, WorldQuery::Stats).await
+}
+
+async fn entity(req: HttpRequest, manager: ManagerData, path: web::Path<(u32,)>) -> HttpResponse {
+    query_world(&manager, &req, WorldQuery::Entity(path.0)).await
+}
+
+async fn chunk(req: HttpRequest, manager: ManagerData, path: web::Path<(i32, i32)>) -> HttpResponse {
+    query_world(&manager, &req, WorldQuery::Chunk(path.0, path.1)).await
+}
+
+async fn path_stats(req: HttpRequest, manager: ManagerData) -> HttpResponse {
+    query_world(&manager, &req, WorldQuery::PathStats).await
+}
+
+async fn tile(req: HttpRequest, manager: ManagerData, path: web::Path<(i32, i32)>) -> HttpResponse {
+    query_world(&manager, &req, WorldQuery::Tile(path.0, path.1)).await
+}
+
+async fn parcel(req: HttpRequest, manager: ManagerData, path: web::Path<(i32, i32)>) -> HttpResponse {
+    query_world(&manager, &req, WorldQuery::Parcel(path.0, path.1)).await
+}
+
+async fn owner_parcels(req: HttpRequest, manager: ManagerData, path: web::Path<(u32,)>) -> HttpResponse {
+    query_world(&manager, &req, WorldQuery::OwnerParcels(path.0)).await
+}
+
+async fn building(req: HttpRequest, manager: ManagerData, path: web::Path<(u32,)>) -> HttpResponse {
+    query_world(&manager, &req, WorldQuery::Building(path.0)).await
+}
+
+async fn owner_buildings(req: HttpRequest, manager: ManagerData, path: web::Path<(u32,)>) -> HttpResponse {
+    query_world(&manager, &req, WorldQuery::OwnerBuildings(path.0)).await
+}
+
+async fn despawn(req: HttpRequest, manager: ManagerData, path: web::Path<(u32,)>) -> HttpResponse {
+    if !authorized(&req) {
+        return forbidden();
+    }
+    match CommandRegistry::with_builtins().parse(&format!("despawn {}", path.0)) {
+        Ok(cmd) => run_command(&manager, selected_world(&req), cmd).await,
+        Err(e) => error_response(&e),
+    }
+}
+
+async fn teleport(req: HttpRequest, manager: ManagerData, path: web::Path<(u32,)>, query: web::Query<TeleportQuery>) -> HttpResponse {
+    if !authorized(&req) {
+        return forbidden();
+    }
+    match CommandRegistry::with_builtins().parse(&format!("teleport {} {} {}", path.0, query.x, query.y)) {
+        Ok(cmd) => run_command(&manager, selected_world(&req), cmd).await,
+        Err(e) => error_response(&e),
+    }
+}
+
+async fn set_tile(req: HttpRequest, manager: ManagerData, path: web::Path<(i32, i32)>, query: web::Query<TileQuery>) -> HttpResponse {
+    if !authorized(&req) {
+        return forbidden();
+    }
+    match CommandRegistry::with_builtins().parse(&format!("tile {} {} {}", path.0, path.1, query.kind)) {
+        Ok(cmd) => run_command(&manager, selected_world(&req), cmd).await,
+        Err(e) => error_response(&e),
+    }
+}
+
+/// Runs one console command line sent as the request body
+async fn command(req: HttpRequest, manager: ManagerData, body: String) -> HttpResponse {
+    if !authorized(&req) {
+        return forbidden();
+    }
+    let (prefix, line) = world_manager::split_world_prefix(&body);
+    match CommandRegistry::with_builtins().dispatch(line) {
+        Ok(Dispatch::Reply(text)) => HttpResponse::Ok().json(json!({ "result": text })),
+        Ok(Dispatch::World(cmd)) => run_command(&manager, prefix.or_else(|| selected_world(&req)), cmd).await,
+        Ok(Dispatch::Manager(cmd)) => match manager.send(cmd).await {
+            Ok(Ok(text)) => HttpResponse::Ok().json(json!({ "result": text })),
+            Ok(Err(e)) => error_response(&e),
+            Err(_) => unavailable(),
+        },
+        Ok(Dispatch::Sync(cmd)) => HttpResponse::BadRequest().json(json!({ "error": format!("{}: sync needs a WebSocket session", cmd.name) })),
+        Err(e) => error_response(&e),
+    }
+}
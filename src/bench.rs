@@ -0,0 +1,163 @@
+use std::time::{Duration, Instant};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use lunacia::ecs::admin;
+use lunacia::ecs::astar::{SearchOptions};
+use lunacia::ecs::jps;
+use lunacia::ecs::pathing;
+use lunacia::ecs::components::{*};
+use lunacia::ecs::submap::{TileMap};
+use lunacia::ecs::types::{*};
+use lunacia::simulation::{HeadlessReport, Simulation};
+use lunacia::config::{ServerConfig};
+
+const BENCH_SEED: u64 = 42;
+
+fn print_report(label: &str, sim: &Simulation, report: &HeadlessReport) {
+    println!("{}: {} ticks in {:?}, mean {:?}, min {:?}, max {:?}",
+        label, report.ticks, report.total, report.mean(), report.min, report.max);
+    if let Some(metrics) = sim.metrics() {
+        println!("{}", metrics.render_summary());
+    }
+}
+
+/// Builds the default world and runs `ticks` updates as fast as possible
+pub fn run_headless(config: &ServerConfig, ticks: u32) {
+    let mut sim = match Simulation::new(config) {
+        Ok(sim) => sim,
+        Err(e) => {
+            println!("error loading world: {:?}", e);
+            return;
+        }
+    };
+    let report = sim.run_ticks(ticks);
+    print_report("headless", &sim, &report);
+}
+
+/// The configured map and its road tiles, None when there is nothing to search
+fn load_roads(config: &ServerConfig) -> Option<(TileMap, Vec<(i32, i32)>)> {
+    let mut tm = TileMap::new(config.map_width, config.map_height);
+    tm.topology = config.movement_topology();
+    if let Err(e) = tm.load_map_from(&config.map_path) {
+        println!("error loading map: {:?}", e);
+        return None;
+    }
+    let mut roads = Vec::new();
+    for y in 1..tm.h as i32 - 1 {
+        for x in 1..tm.w as i32 - 1 {
+            if tm.is_road_tile(&(x, y)) {
+                roads.push((x, y));
+            }
+        }
+    }
+    if roads.len() < 2 {
+        println!("no road tiles to search between");
+        return None;
+    }
+    Some((tm, roads))
+}
+
+fn random_pairs(roads: &Vec<(i32, i32)>, searches: u32) -> Vec<((i32, i32), (i32, i32))> {
+    let mut rng = StdRng::seed_from_u64(BENCH_SEED);
+    (0..searches).map(|_| (roads[rng.gen_range(0, roads.len())], roads[rng.gen_range(0, roads.len())])).collect()
+}
+
+fn print_search_report(label: &str, searches: u32, total: Duration, found: u32, total_expanded: usize) {
+    println!("{}: {} searches in {:?}, mean {:?}, {} found, mean {} expanded",
+        label, searches, total, total / searches.max(1), found, total_expanded / searches.max(1) as usize);
+}
+
+/// A* between random pairs of road tiles of the configured map
+pub fn bench_astar(config: &ServerConfig, searches: u32) {
+    let (tm, roads) = match load_roads(config) {
+        Some(loaded) => loaded,
+        None => return,
+    };
+    let mut total = Duration::from_millis(0);
+    let mut found = 0;
+    let mut total_expanded = 0;
+    for (start, goal) in random_pairs(&roads, searches) {
+        let t = Instant::now();
+        let search = pathing::astar_path(&tm, &start, &goal, &SearchOptions::default());
+        total += t.elapsed();
+        total_expanded += search.expanded;
+        if search.path.is_some() {
+            found += 1;
+        }
+    }
+    print_search_report("astar", searches, total, found, total_expanded);
+}
+
+/// Jump Point Search against A* on the same pairs of road tiles
+pub fn bench_jps(config: &ServerConfig, searches: u32) {
+    let (tm, roads) = match load_roads(config) {
+        Some(loaded) => loaded,
+        None => return,
+    };
+    let (mut astar_total, mut jps_total) = (Duration::from_millis(0), Duration::from_millis(0));
+    let (mut astar_found, mut jps_found) = (0, 0);
+    let (mut astar_expanded, mut jps_expanded) = (0, 0);
+    let mut longer = 0;
+    for (start, goal) in random_pairs(&roads, searches) {
+        let t = Instant::now();
+        let reference = pathing::astar_path(&tm, &start, &goal, &SearchOptions::default());
+        astar_total += t.elapsed();
+        astar_expanded += reference.expanded;
+
+        let t = Instant::now();
+        let result = jps::jps(&tm, &start, &goal, None, &mut jps_expanded);
+        jps_total += t.elapsed();
+
+        if let Some((_, cost)) = &reference.path {
+            astar_found += 1;
+            if let Some((_, jps_cost)) = &result {
+                if jps_cost > cost {
+                    longer += 1;
+                }
+            }
+        }
+        if result.is_some() {
+            jps_found += 1;
+        }
+    }
+    print_search_report("astar", searches, astar_total, astar_found, astar_expanded);
+    print_search_report("jps", searches, jps_total, jps_found, jps_expanded);
+    println!("jps: {} paths costlier than A* by staying on the road", longer);
+}
+
+/// Full updates of the default world with `extra_axies` more axies spread over the whole map
+pub fn bench_tick(config: &ServerConfig, ticks: u32, extra_axies: u32) {
+    let mut sim = match Simulation::new(config) {
+        Ok(sim) => sim,
+        Err(e) => {
+            println!("error loading world: {:?}", e);
+            return;
+        }
+    };
+
+    let mut lands = Vec::new();
+    if let Some(tm) = sim.resources.get::<TileMapResource>() {
+        let tm = &tm.0;
+        for y in 1..tm.h as i32 - 1 {
+            for x in 1..tm.w as i32 - 1 {
+                if tm.is_land_tile(&(x, y)) && tm.can_move_to(&(x, y)) {
+                    lands.push((x, y));
+                }
+            }
+        }
+    }
+    let mut rng = StdRng::seed_from_u64(BENCH_SEED);
+    let mut spawned = 0;
+    while spawned < extra_axies && lands.len() > 0 {
+        let (x, y) = lands[rng.gen_range(0, lands.len())];
+        if admin::spawn(&mut sim.world, &sim.resources, UnitModelType::Axie as u32, x, y, 1).is_ok() {
+            spawned += 1;
+        }
+    }
+
+    let report = sim.run_ticks(ticks);
+    let label = format!("tick (+{} axies)", spawned);
+    print_report(&label, &sim, &report);
+}
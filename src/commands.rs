@@ -0,0 +1,471 @@
+use actix::prelude::*;
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArgKind {
+    Int,
+    UInt,
+    Float,
+    Word,
+}
+
+impl ArgKind {
+    fn describe(&self) -> &'static str {
+        match self {
+            ArgKind::Int => "integer",
+            ArgKind::UInt => "positive integer",
+            ArgKind::Float => "number",
+            ArgKind::Word => "word",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ArgSpec {
+    pub name: &'static str,
+    pub kind: ArgKind,
+    pub optional: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct CommandSpec {
+    pub name: &'static str,
+    pub aliases: Vec<&'static str>,
+    pub args: Vec<ArgSpec>,
+    pub help: &'static str,
+    /// Changes the world or the server, only run for sessions holding the admin token
+    pub admin: bool,
+}
+
+impl CommandSpec {
+    pub fn new(name: &'static str, help: &'static str) -> Self {
+        CommandSpec { name, aliases: Vec::new(), args: Vec::new(), help, admin: false }
+    }
+
+    pub fn admin(mut self) -> Self {
+        self.admin = true;
+        self
+    }
+
+    pub fn alias(mut self, alias: &'static str) -> Self {
+        self.aliases.push(alias);
+        self
+    }
+
+    pub fn arg(mut self, name: &'static str, kind: ArgKind) -> Self {
+        self.args.push(ArgSpec { name, kind, optional: false });
+        self
+    }
+
+    pub fn opt_arg(mut self, name: &'static str, kind: ArgKind) -> Self {
+        self.args.push(ArgSpec { name, kind, optional: true });
+        self
+    }
+
+    pub fn usage(&self) -> String {
+        let mut usage = String::from(self.name);
+        for arg in self.args.iter() {
+            if arg.optional {
+                usage += &format!(" [{}]", arg.name);
+            } else {
+                usage += &format!(" <{}>", arg.name);
+            }
+        }
+        usage
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ArgValue {
+    Int(i64),
+    Float(f64),
+    Word(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CommandError {
+    Unknown(String),
+    MissingArg { command: String, arg: String },
+    InvalidArg { command: String, arg: String, value: String, expected: String },
+    TooManyArgs { command: String, usage: String },
+    NotFound(String),
+    Failed(String),
+    /// An admin command sent without the admin token
+    Forbidden(String),
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CommandError::Unknown(name) => write!(f, "unknown command '{}', try 'help'", name),
+            CommandError::MissingArg { command, arg } => write!(f, "{}: missing argument <{}>", command, arg),
+            CommandError::InvalidArg { command, arg, value, expected } => {
+                write!(f, "{}: <{}> expects a {}, got '{}'", command, arg, expected, value)
+            },
+            CommandError::TooManyArgs { command, usage } => write!(f, "{}: too many arguments, usage: {}", command, usage),
+            CommandError::NotFound(what) => write!(f, "not found: {}", what),
+            CommandError::Failed(reason) => write!(f, "failed: {}", reason),
+            CommandError::Forbidden(command) => write!(f, "{}: admin token required", command),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CommandArgs {
+    command: String,
+    values: HashMap<&'static str, ArgValue>,
+}
+
+impl CommandArgs {
+    fn missing(&self, name: &str) -> CommandError {
+        CommandError::MissingArg { command: self.command.clone(), arg: name.to_string() }
+    }
+
+    pub fn opt_int(&self, name: &str) -> Option<i32> {
+        match self.values.get(name) {
+            Some(ArgValue::Int(v)) => Some(*v as i32),
+            _ => None,
+        }
+    }
+
+    pub fn opt_uint(&self, name: &str) -> Option<u32> {
+        self.opt_int(name).map(|v| v as u32)
+    }
+
+    pub fn opt_float(&self, name: &str) -> Option<f32> {
+        match self.values.get(name) {
+            Some(ArgValue::Float(v)) => Some(*v as f32),
+            Some(ArgValue::Int(v)) => Some(*v as f32),
+            _ => None,
+        }
+    }
+
+    pub fn opt_word(&self, name: &str) -> Option<&str> {
+        match self.values.get(name) {
+            Some(ArgValue::Word(v)) => Some(v.as_str()),
+            _ => None,
+        }
+    }
+
+    pub fn int(&self, name: &str) -> Result<i32, CommandError> {
+        self.opt_int(name).ok_or_else(|| self.missing(name))
+    }
+
+    pub fn uint(&self, name: &str) -> Result<u32, CommandError> {
+        self.opt_uint(name).ok_or_else(|| self.missing(name))
+    }
+
+    pub fn float(&self, name: &str) -> Result<f32, CommandError> {
+        self.opt_float(name).ok_or_else(|| self.missing(name))
+    }
+
+    pub fn word(&self, name: &str) -> Result<&str, CommandError> {
+        self.opt_word(name).ok_or_else(|| self.missing(name))
+    }
+}
+
+/// A parsed and validated command, ready to be run by the world
+#[derive(Clone, Debug)]
+pub struct AdminCommand {
+    pub name: &'static str,
+    pub args: CommandArgs,
+}
+
+impl Message for AdminCommand {
+    type Result = Result<String, CommandError>;
+}
+
+/// Commands run by the world manager instead of a world
+const MANAGER_COMMANDS: [&str; 3] = ["worlds", "create_world", "stop_world"];
+
+/// Commands about the sync client of the session sending them
+const SYNC_COMMANDS: [&str; 4] = ["sub", "unsub", "ack", "resync"];
+
+/// What a transport should do with a line of input
+pub enum Dispatch {
+    /// Answered by the registry itself, e.g. help
+    Reply(String),
+    World(AdminCommand),
+    /// About the worlds themselves, for the world manager
+    Manager(AdminCommand),
+    /// For the world, on behalf of the sync client bound to the session
+    Sync(AdminCommand),
+}
+
+#[derive(Clone, Default)]
+pub struct CommandRegistry {
+    specs: Vec<CommandSpec>,
+}
+
+impl CommandRegistry {
+    pub fn new() -> Self {
+        CommandRegistry { specs: Vec::new() }
+    }
+
+    pub fn with_builtins() -> Self {
+        let mut registry = CommandRegistry::new();
+        registry.register(CommandSpec::new("help", "List commands or show the usage of one, prefix a command with @world to run it there")
+            .opt_arg("command", ArgKind::Word));
+        registry.register(CommandSpec::new("worlds", "List the running worlds"));
+        registry.register(CommandSpec::new("create_world", "Start a world from a config file")
+            .admin()
+            .arg("name", ArgKind::Word)
+            .arg("config", ArgKind::Word));
+        registry.register(CommandSpec::new("stop_world", "Stop a world")
+            .admin()
+            .arg("name", ArgKind::Word));
+        registry.register(CommandSpec::new("focus", "Print the entities around a land position")
+            .alias("i")
+            .arg("x", ArgKind::Int)
+            .arg("y", ArgKind::Int));
+        registry.register(CommandSpec::new("gather", "Request an axie of owner 1 to gather resources")
+            .admin()
+            .alias("g")
+            .arg("axie", ArgKind::UInt));
+        registry.register(CommandSpec::new("spawn", "Spawn an axie, chimera or resource node")
+            .admin()
+            .arg("model", ArgKind::Word)
+            .arg("x", ArgKind::Int)
+            .arg("y", ArgKind::Int)
+            .opt_arg("owner", ArgKind::UInt));
+        registry.register(CommandSpec::new("despawn", "Delete an entity")
+            .admin()
+            .arg("id", ArgKind::UInt));
+        registry.register(CommandSpec::new("teleport", "Move an entity to a land position, dropping its current path and goal")
+            .admin()
+            .alias("tp")
+            .arg("id", ArgKind::UInt)
+            .arg("x", ArgKind::Int)
+            .arg("y", ArgKind::Int));
+        registry.register(CommandSpec::new("inspect", "Print the components of an entity")
+            .arg("id", ArgKind::UInt));
+        registry.register(CommandSpec::new("chunk", "List the entities of the chunk containing a land position")
+            .arg("x", ArgKind::Int)
+            .arg("y", ArgKind::Int));
+        registry.register(CommandSpec::new("parcel", "Print the land parcel containing a land position")
+            .arg("x", ArgKind::Int)
+            .arg("y", ArgKind::Int));
+        registry.register(CommandSpec::new("parcels", "List the land parcels of an owner")
+            .arg("owner", ArgKind::UInt));
+        registry.register(CommandSpec::new("build", "Start a storage or house on a parcel of the owner, paid from their storages")
+            .admin()
+            .arg("kind", ArgKind::Word)
+            .arg("x", ArgKind::Int)
+            .arg("y", ArgKind::Int)
+            .opt_arg("owner", ArgKind::UInt));
+        registry.register(CommandSpec::new("building", "Print a building and its construction progress")
+            .arg("id", ArgKind::UInt));
+        registry.register(CommandSpec::new("buildings", "List the buildings and stock of an owner")
+            .arg("owner", ArgKind::UInt));
+        registry.register(CommandSpec::new("tile", "Change a tile: road, alley, land, resource or clear")
+            .admin()
+            .arg("x", ArgKind::Int)
+            .arg("y", ArgKind::Int)
+            .arg("kind", ArgKind::Word));
+        registry.register(CommandSpec::new("render", "Draw a region with entities, paths and chunks, as ASCII or to a .ppm file in render_dir")
+            .admin()
+            .arg("x", ArgKind::Int)
+            .arg("y", ArgKind::Int)
+            .opt_arg("w", ArgKind::UInt)
+            .opt_arg("h", ArgKind::UInt)
+            .opt_arg("file", ArgKind::Word));
+        registry.register(CommandSpec::new("set", "Change a setting: fixed_time_ms, max_catch_up_steps, time_scale")
+            .admin()
+            .arg("key", ArgKind::Word)
+            .arg("value", ArgKind::Float));
+        registry.register(CommandSpec::new("stats", "Print world statistics"));
+        registry.register(CommandSpec::new("pause", "Pause the world clock")
+            .admin());
+        registry.register(CommandSpec::new("resume", "Resume the world clock")
+            .admin());
+        registry.register(CommandSpec::new("step", "Run frames right away")
+            .admin()
+            .opt_arg("frames", ArgKind::UInt));
+        registry.register(CommandSpec::new("speed", "Set simulated time per real time")
+            .admin()
+            .arg("scale", ArgKind::Float));
+        registry.register(CommandSpec::new("clock", "Print the world clock"));
+        registry.register(CommandSpec::new("metrics", "Dump timings and counters, format: summary or prometheus")
+            .opt_arg("format", ArgKind::Word));
+        registry.register(CommandSpec::new("sub", "Sync the chunks around a position to this session")
+            .admin()
+            .arg("x", ArgKind::Int)
+            .arg("y", ArgKind::Int)
+            .opt_arg("range", ArgKind::UInt));
+        registry.register(CommandSpec::new("unsub", "Stop syncing chunks to this session")
+            .admin());
+        registry.register(CommandSpec::new("ack", "Acknowledge the sync messages of this session up to seq")
+            .admin()
+            .arg("seq", ArgKind::UInt));
+        registry.register(CommandSpec::new("resync", "Send fresh snapshots to this session")
+            .admin());
+        registry
+    }
+
+    /// Adds a command, replacing any command with the same name
+    pub fn register(&mut self, spec: CommandSpec) {
+        self.specs.retain(|s| s.name != spec.name);
+        self.specs.push(spec);
+    }
+
+    pub fn find(&self, name: &str) -> Option<&CommandSpec> {
+        self.specs.iter().find(|s| s.name == name || s.aliases.contains(&name))
+    }
+
+    pub fn help(&self, name: Option<&str>) -> Result<String, CommandError> {
+        match name {
+            Some(name) => {
+                let spec = self.find(name).ok_or_else(|| CommandError::Unknown(name.to_string()))?;
+                Ok(format!("{}\n  {}", spec.usage(), spec.help))
+            },
+            None => {
+                let lines: Vec<String> = self.specs.iter()
+                    .map(|spec| format!("{:<32} {}", spec.usage(), spec.help))
+                    .collect();
+                Ok(lines.join("\n"))
+            }
+        }
+    }
+
+    pub fn parse(&self, line: &str) -> Result<AdminCommand, CommandError> {
+        let mut iter = line.split_ascii_whitespace();
+        let name = iter.next().unwrap_or("");
+        let spec = self.find(name).ok_or_else(|| CommandError::Unknown(name.to_string()))?;
+
+        let mut args = CommandArgs { command: spec.name.to_string(), values: HashMap::new() };
+        for arg in spec.args.iter() {
+            let value = match iter.next() {
+                Some(v) => v,
+                None => {
+                    if arg.optional {
+                        break;
+                    }
+                    return Err(args.missing(arg.name));
+                }
+            };
+            let invalid = || CommandError::InvalidArg {
+                command: spec.name.to_string(),
+                arg: arg.name.to_string(),
+                value: value.to_string(),
+                expected: arg.kind.describe().to_string(),
+            };
+            let parsed = match arg.kind {
+                ArgKind::Int => ArgValue::Int(value.parse::<i32>().map_err(|_| invalid())? as i64),
+                ArgKind::UInt => ArgValue::Int(value.parse::<u32>().map_err(|_| invalid())? as i64),
+                ArgKind::Float => ArgValue::Float(value.parse::<f64>().map_err(|_| invalid())?),
+                ArgKind::Word => ArgValue::Word(value.to_string()),
+            };
+            args.values.insert(arg.name, parsed);
+        }
+        if iter.next().is_some() {
+            return Err(CommandError::TooManyArgs { command: spec.name.to_string(), usage: spec.usage() });
+        }
+
+        Ok(AdminCommand { name: spec.name, args })
+    }
+
+    pub fn dispatch(&self, line: &str) -> Result<Dispatch, CommandError> {
+        self.dispatch_as(line, true)
+    }
+
+    /// Same as `dispatch`, admin commands are refused unless `admin` is set
+    pub fn dispatch_as(&self, line: &str, admin: bool) -> Result<Dispatch, CommandError> {
+        let cmd = self.parse(line)?;
+        if !admin && self.find(cmd.name).map_or(false, |spec| spec.admin) {
+            return Err(CommandError::Forbidden(cmd.name.to_string()));
+        }
+        match cmd.name {
+            "help" => Ok(Dispatch::Reply(self.help(cmd.args.opt_word("command"))?)),
+            name if MANAGER_COMMANDS.contains(&name) => Ok(Dispatch::Manager(cmd)),
+            name if SYNC_COMMANDS.contains(&name) => Ok(Dispatch::Sync(cmd)),
+            _ => Ok(Dispatch::World(cmd)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world_manager::split_world_prefix;
+
+    fn command(dispatch: Result<Dispatch, CommandError>) -> &'static str {
+        match dispatch {
+            Ok(Dispatch::World(cmd)) => cmd.name,
+            Ok(_) => panic!("not a world command"),
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    #[test]
+    fn parses_aliases_and_typed_arguments() {
+        let registry = CommandRegistry::with_builtins();
+        let cmd = registry.parse("tp 12 -3 4").unwrap();
+        assert_eq!(cmd.name, "teleport");
+        assert_eq!((cmd.args.uint("id"), cmd.args.int("x"), cmd.args.int("y")), (Ok(12), Ok(-3), Ok(4)));
+
+        let cmd = registry.parse("set time_scale 2").unwrap();
+        assert_eq!((cmd.args.word("key"), cmd.args.float("value")), (Ok("time_scale"), Ok(2.0)));
+    }
+
+    #[test]
+    fn optional_arguments_may_be_left_out() {
+        let registry = CommandRegistry::with_builtins();
+        let cmd = registry.parse("spawn axie 3 4").unwrap();
+        assert_eq!(cmd.args.opt_uint("owner"), None);
+        assert_eq!(cmd.args.word("model"), Ok("axie"));
+        let cmd = registry.parse("spawn axie 3 4 2").unwrap();
+        assert_eq!(cmd.args.opt_uint("owner"), Some(2));
+        assert_eq!(registry.parse("step").unwrap().args.opt_uint("frames"), None);
+    }
+
+    #[test]
+    fn reports_bad_arguments() {
+        let registry = CommandRegistry::with_builtins();
+        assert_eq!(registry.parse("focus 3").unwrap_err(),
+            CommandError::MissingArg { command: "focus".to_string(), arg: "y".to_string() });
+        assert_eq!(registry.parse("despawn -1").unwrap_err(), CommandError::InvalidArg {
+            command: "despawn".to_string(),
+            arg: "id".to_string(),
+            value: "-1".to_string(),
+            expected: "positive integer".to_string(),
+        });
+        assert!(matches!(registry.parse("speed fast"), Err(CommandError::InvalidArg { .. })));
+        assert!(matches!(registry.parse("clock now"), Err(CommandError::TooManyArgs { .. })));
+        assert_eq!(registry.parse("fly 1 2").unwrap_err(), CommandError::Unknown("fly".to_string()));
+        assert_eq!(registry.parse("").unwrap_err(), CommandError::Unknown("".to_string()));
+    }
+
+    #[test]
+    fn refuses_admin_commands_without_the_token() {
+        let registry = CommandRegistry::with_builtins();
+        for line in ["tile 3 4 road", "g 1", "sub 3 4", "ack 2", "stop_world other"].iter() {
+            let name = registry.parse(line).unwrap().name;
+            assert!(matches!(registry.dispatch_as(line, false), Err(CommandError::Forbidden(n)) if n == name), "{}", line);
+            assert!(registry.dispatch_as(line, true).is_ok(), "{}", line);
+        }
+        assert_eq!(command(registry.dispatch_as("i 3 4", false)), "focus");
+    }
+
+    #[test]
+    fn routes_each_command_to_its_handler() {
+        let registry = CommandRegistry::with_builtins();
+        match registry.dispatch("help tp") {
+            Ok(Dispatch::Reply(text)) => assert!(text.starts_with("teleport <id> <x> <y>")),
+            _ => panic!("help is answered by the registry"),
+        }
+        assert!(matches!(registry.dispatch("worlds"), Ok(Dispatch::Manager(cmd)) if cmd.name == "worlds"));
+        assert!(matches!(registry.dispatch("create_world w2 w2.toml"), Ok(Dispatch::Manager(_))));
+        assert!(matches!(registry.dispatch("resync"), Ok(Dispatch::Sync(cmd)) if cmd.name == "resync"));
+        assert!(matches!(registry.dispatch("unsub"), Ok(Dispatch::Sync(_))));
+        assert_eq!(command(registry.dispatch("stats")), "stats");
+        assert!(matches!(registry.dispatch("help fly"), Err(CommandError::Unknown(_))));
+    }
+
+    #[test]
+    fn splits_the_world_prefix() {
+        assert_eq!(split_world_prefix("@w2 stats"), (Some("w2".to_string()), " stats"));
+        assert_eq!(split_world_prefix("  @w2"), (Some("w2".to_string()), ""));
+        assert_eq!(split_world_prefix("stats @w2"), (None, "stats @w2"));
+    }
+}
@@ -0,0 +1,399 @@
+//! Server settings, read from a TOML file then overridden by
+//! `LUNACIA_<KEY>` environment variables and `--<key>=<value>` arguments.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::ecs::pathing::{PathAlgorithm, PathFailurePolicy, PathFallback};
+use crate::ecs::astar::{SearchOptions};
+use crate::ecs::submap::{CornerCutting, MovementTopology, STRAIGHT_STEP_COST};
+use crate::ecs::types::{GameConfigResource};
+
+pub const DEFAULT_CONFIG_PATH: &str = "lunacia.toml";
+const CONFIG_PATH_ENV: &str = "LUNACIA_CONFIG";
+const ENV_PREFIX: &str = "LUNACIA_";
+
+/// Longest fixed step, in ms
+pub const MAX_FIXED_TIME_MS: u64 = 10_000;
+/// Most frames one update catches up at time scale 1
+pub const MAX_CATCH_UP_STEPS: u32 = 60;
+
+const KEYS: [&str; 29] = [
+    "fixed_time_ms",
+    "max_catch_up_steps",
+    "map_path",
+    "map_width",
+    "map_height",
+    "spawn_min_x",
+    "spawn_min_y",
+    "spawn_max_x",
+    "spawn_max_y",
+    "heartbeat_interval_secs",
+    "client_timeout_secs",
+    "auto_collect_chance",
+    "auto_collect_search_range",
+    "auto_collect_max_cost",
+    "http_bind",
+    "render_dir",
+    "scenario_path",
+    "movement_directions",
+    "diagonal_cost",
+    "corner_cutting",
+    "path_algorithm",
+    "flow_field_demand",
+    "max_flow_fields",
+    "path_retries",
+    "path_retry_ms",
+    "path_fallback",
+    "path_max_expanded",
+    "path_max_cost",
+    "path_tie_break",
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConfigError {
+    Io(String, String),
+    Parse(String),
+    UnknownKey(String),
+    InvalidValue { key: String, value: String },
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "can not read {}: {}", path, e),
+            ConfigError::Parse(e) => write!(f, "invalid config file: {}", e),
+            ConfigError::UnknownKey(key) => write!(f, "unknown setting '{}'", key),
+            ConfigError::InvalidValue { key, value } => write!(f, "invalid value '{}' for {}", value, key),
+            ConfigError::Invalid(reason) => write!(f, "invalid config: {}", reason),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Length of one world update
+    pub fixed_time_ms: u64,
+    /// Updates run at most per wake up before time is dropped
+    pub max_catch_up_steps: u32,
+    pub map_path: String,
+    pub map_width: usize,
+    pub map_height: usize,
+    /// Land tiles in [min, max) get an axie at start
+    pub spawn_min_x: i32,
+    pub spawn_min_y: i32,
+    pub spawn_max_x: i32,
+    pub spawn_max_y: i32,
+    pub heartbeat_interval_secs: u64,
+    pub client_timeout_secs: u64,
+    /// Percent chance per update that an idle AutoCollect axie looks for a resource node
+    pub auto_collect_chance: u32,
+    /// How many chunk rings around home are searched for resource nodes
+    pub auto_collect_search_range: u32,
+    /// Resource nodes further by path cost are ignored, 0 for no limit. A road step costs 10.
+    pub auto_collect_max_cost: u32,
+    pub http_bind: String,
+    /// Directory `render` writes its image files to
+    pub render_dir: String,
+    /// Initial entities, the spawn area is filled when not set
+    pub scenario_path: Option<String>,
+    /// 4 or 8, the latter adds diagonal steps
+    pub movement_directions: u32,
+    /// Cost of a diagonal step when a straight one costs 10
+    pub diagonal_cost: u32,
+    /// never, one_side or always: when a diagonal step may pass an impassable tile
+    pub corner_cutting: String,
+    /// astar, or jps to search along the roads first
+    pub path_algorithm: String,
+    /// Units heading to the same tile before it gets a flow field, 0 disables them
+    pub flow_field_demand: u32,
+    /// Flow fields kept at once, each holds a cost per map tile
+    pub max_flow_fields: usize,
+    /// Times a unit searches again when no path reaches its target
+    pub path_retries: u32,
+    /// Wait before the first retry, doubled for each of the next ones
+    pub path_retry_ms: u64,
+    /// none, or nearest to walk to the reachable tile closest to the target once retries are spent
+    pub path_fallback: String,
+    /// Tiles an A* search may expand before giving up, 0 for no limit
+    pub path_max_expanded: usize,
+    /// Paths costing more are not searched, 0 for no limit. A road step costs 10.
+    pub path_max_cost: u32,
+    /// Expand first the tiles closest to the target between equally good ones
+    pub path_tie_break: bool,
+    /// More worlds started next to the main one, name -> config file.
+    /// Only the world settings of those files are used.
+    pub worlds: BTreeMap<String, String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            fixed_time_ms: 200,
+            max_catch_up_steps: 5,
+            map_path: "sub-map.bin".to_string(),
+            map_width: 390,
+            map_height: 390,
+            spawn_min_x: 30,
+            spawn_min_y: 30,
+            spawn_max_x: 100,
+            spawn_max_y: 100,
+            heartbeat_interval_secs: 5,
+            client_timeout_secs: 10,
+            auto_collect_chance: 11,
+            auto_collect_search_range: 10,
+            auto_collect_max_cost: 0,
+            http_bind: "127.0.0.1:8080".to_string(),
+            render_dir: "renders".to_string(),
+            scenario_path: None,
+            movement_directions: 4,
+            diagonal_cost: 14,
+            corner_cutting: "never".to_string(),
+            path_algorithm: "astar".to_string(),
+            flow_field_demand: 8,
+            max_flow_fields: 16,
+            path_retries: 2,
+            path_retry_ms: 1000,
+            path_fallback: "nearest".to_string(),
+            path_max_expanded: 50000,
+            path_max_cost: 0,
+            path_tie_break: false,
+            worlds: BTreeMap::new(),
+        }
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, ConfigError> {
+    value.trim().parse::<T>().map_err(|_| ConfigError::InvalidValue { key: key.to_string(), value: value.to_string() })
+}
+
+impl ServerConfig {
+    pub fn from_file(path: &str) -> Result<Self, ConfigError> {
+        let text = fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_string(), e.to_string()))?;
+        toml::from_str(&text).map_err(|e| ConfigError::Parse(e.to_string()))
+    }
+
+    /// Reads the config file, applies env then command line overrides and validates the result.
+    /// Returns the arguments that are not settings.
+    pub fn load(args: &[String]) -> Result<(Self, Vec<String>), ConfigError> {
+        let (config, positional) = ServerConfig::load_settings(args)?;
+        config.check_files()?;
+        Ok((config, positional))
+    }
+
+    /// Same as `load` without looking for the map, scenario and world files,
+    /// for commands that do not read them
+    pub fn load_settings(args: &[String]) -> Result<(Self, Vec<String>), ConfigError> {
+        let mut path = env::var(CONFIG_PATH_ENV).ok();
+        let mut overrides = Vec::new();
+        let mut positional = Vec::new();
+
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            if !arg.starts_with("--") {
+                positional.push(arg.clone());
+                continue;
+            }
+            let (key, value) = match arg[2..].find('=') {
+                Some(i) => (arg[2..2 + i].to_string(), arg[3 + i..].to_string()),
+                None => {
+                    let value = iter.next().ok_or_else(|| ConfigError::InvalidValue { key: arg[2..].to_string(), value: String::new() })?;
+                    (arg[2..].to_string(), value.clone())
+                }
+            };
+            let key = key.replace('-', "_");
+            if key == "config" {
+                path = Some(value);
+            } else {
+                overrides.push((key, value));
+            }
+        }
+
+        let mut config = match path {
+            Some(p) => ServerConfig::from_file(&p)?,
+            None => {
+                if Path::new(DEFAULT_CONFIG_PATH).exists() {
+                    ServerConfig::from_file(DEFAULT_CONFIG_PATH)?
+                } else {
+                    ServerConfig::default()
+                }
+            }
+        };
+        for key in KEYS.iter() {
+            if let Ok(value) = env::var(format!("{}{}", ENV_PREFIX, key.to_uppercase())) {
+                config.set(key, &value)?;
+            }
+        }
+        for (key, value) in overrides.iter() {
+            config.set(key, value)?;
+        }
+        config.validate_settings()?;
+        Ok((config, positional))
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        match key {
+            "fixed_time_ms" => self.fixed_time_ms = parse_value(key, value)?,
+            "max_catch_up_steps" => self.max_catch_up_steps = parse_value(key, value)?,
+            "map_path" => self.map_path = value.to_string(),
+            "map_width" => self.map_width = parse_value(key, value)?,
+            "map_height" => self.map_height = parse_value(key, value)?,
+            "spawn_min_x" => self.spawn_min_x = parse_value(key, value)?,
+            "spawn_min_y" => self.spawn_min_y = parse_value(key, value)?,
+            "spawn_max_x" => self.spawn_max_x = parse_value(key, value)?,
+            "spawn_max_y" => self.spawn_max_y = parse_value(key, value)?,
+            "heartbeat_interval_secs" => self.heartbeat_interval_secs = parse_value(key, value)?,
+            "client_timeout_secs" => self.client_timeout_secs = parse_value(key, value)?,
+            "auto_collect_chance" => self.auto_collect_chance = parse_value(key, value)?,
+            "auto_collect_search_range" => self.auto_collect_search_range = parse_value(key, value)?,
+            "auto_collect_max_cost" => self.auto_collect_max_cost = parse_value(key, value)?,
+            "http_bind" => self.http_bind = value.to_string(),
+            "render_dir" => self.render_dir = value.to_string(),
+            "scenario_path" => self.scenario_path = if value.is_empty() { None } else { Some(value.to_string()) },
+            "movement_directions" => self.movement_directions = parse_value(key, value)?,
+            "diagonal_cost" => self.diagonal_cost = parse_value(key, value)?,
+            "corner_cutting" => self.corner_cutting = value.to_string(),
+            "path_algorithm" => self.path_algorithm = value.to_string(),
+            "flow_field_demand" => self.flow_field_demand = parse_value(key, value)?,
+            "max_flow_fields" => self.max_flow_fields = parse_value(key, value)?,
+            "path_retries" => self.path_retries = parse_value(key, value)?,
+            "path_retry_ms" => self.path_retry_ms = parse_value(key, value)?,
+            "path_fallback" => self.path_fallback = value.to_string(),
+            "path_max_expanded" => self.path_max_expanded = parse_value(key, value)?,
+            "path_max_cost" => self.path_max_cost = parse_value(key, value)?,
+            "path_tie_break" => self.path_tie_break = parse_value(key, value)?,
+            _ => return Err(ConfigError::UnknownKey(key.to_string())),
+        }
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        self.validate_settings()?;
+        self.check_files()
+    }
+
+    /// Checks the values, not the files they point to
+    pub fn validate_settings(&self) -> Result<(), ConfigError> {
+        let invalid = |reason: &str| Err(ConfigError::Invalid(reason.to_string()));
+        if self.fixed_time_ms == 0 || self.fixed_time_ms > MAX_FIXED_TIME_MS {
+            return invalid(&format!("fixed_time_ms must be within 1..={}", MAX_FIXED_TIME_MS));
+        }
+        if self.max_catch_up_steps == 0 || self.max_catch_up_steps > MAX_CATCH_UP_STEPS {
+            return invalid(&format!("max_catch_up_steps must be within 1..={}", MAX_CATCH_UP_STEPS));
+        }
+        if self.map_width < 3 || self.map_height < 3 {
+            return invalid("map must be at least 3x3");
+        }
+        if self.spawn_min_x < 0 || self.spawn_min_y < 0
+            || self.spawn_min_x > self.spawn_max_x || self.spawn_min_y > self.spawn_max_y
+            || self.spawn_max_x > self.map_width as i32 || self.spawn_max_y > self.map_height as i32 {
+            return invalid("spawn area must lie inside the map");
+        }
+        if self.heartbeat_interval_secs == 0 || self.client_timeout_secs <= self.heartbeat_interval_secs {
+            return invalid("client_timeout_secs must be longer than a non zero heartbeat_interval_secs");
+        }
+        if self.auto_collect_chance > 100 {
+            return invalid("auto_collect_chance is a percentage");
+        }
+        if self.render_dir.is_empty() {
+            return invalid("render_dir must be set");
+        }
+        if self.auto_collect_search_range == 0 {
+            return invalid("auto_collect_search_range must be at least 1");
+        }
+        if self.movement_directions != 4 && self.movement_directions != 8 {
+            return invalid("movement_directions must be 4 or 8");
+        }
+        if self.diagonal_cost < STRAIGHT_STEP_COST || self.diagonal_cost > 2 * STRAIGHT_STEP_COST {
+            return invalid("diagonal_cost must be within 10..=20");
+        }
+        if CornerCutting::from_name(&self.corner_cutting).is_none() {
+            return invalid("corner_cutting must be never, one_side or always");
+        }
+        if PathAlgorithm::from_name(&self.path_algorithm).is_none() {
+            return invalid("path_algorithm must be astar or jps");
+        }
+        if PathFallback::from_name(&self.path_fallback).is_none() {
+            return invalid("path_fallback must be none or nearest");
+        }
+        if self.path_retries > 0 && self.path_retry_ms == 0 {
+            return invalid("path_retry_ms must be at least 1 when path_retries is set");
+        }
+        Ok(())
+    }
+
+    /// The map, world configs and scenario exist
+    pub fn check_files(&self) -> Result<(), ConfigError> {
+        let invalid = |reason: &str| Err(ConfigError::Invalid(reason.to_string()));
+        if !Path::new(&self.map_path).exists() {
+            return invalid(&format!("map file {} does not exist", self.map_path));
+        }
+        for (name, path) in self.worlds.iter() {
+            if !Path::new(path).exists() {
+                return invalid(&format!("config file {} of world {} does not exist", path, name));
+            }
+        }
+        if let Some(path) = &self.scenario_path {
+            if !Path::new(path).exists() {
+                return invalid(&format!("scenario file {} does not exist", path));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn heartbeat_interval(&self) -> Duration {
+        Duration::from_secs(self.heartbeat_interval_secs)
+    }
+
+    pub fn movement_topology(&self) -> MovementTopology {
+        MovementTopology {
+            diagonal: self.movement_directions == 8,
+            diagonal_cost: self.diagonal_cost,
+            corner_cutting: CornerCutting::from_name(&self.corner_cutting).unwrap_or(CornerCutting::Never),
+        }
+    }
+
+    pub fn path_failure_policy(&self) -> PathFailurePolicy {
+        PathFailurePolicy {
+            retries: self.path_retries,
+            retry_ms: self.path_retry_ms,
+            fallback: PathFallback::from_name(&self.path_fallback).unwrap_or(PathFallback::None),
+        }
+    }
+
+    pub fn path_search_options(&self) -> SearchOptions<u32> {
+        SearchOptions {
+            max_expanded: if self.path_max_expanded > 0 { Some(self.path_max_expanded) } else { None },
+            max_cost: if self.path_max_cost > 0 { Some(self.path_max_cost) } else { None },
+            tie_break: self.path_tie_break,
+        }
+    }
+
+    pub fn client_timeout(&self) -> Duration {
+        Duration::from_secs(self.client_timeout_secs)
+    }
+
+    pub fn game_config(&self) -> GameConfigResource {
+        GameConfigResource {
+            number_of_updates: 0,
+            fixed_time_ms: self.fixed_time_ms,
+            map_width: self.map_width,
+            map_height: self.map_height,
+            auto_collect_chance: self.auto_collect_chance,
+            auto_collect_search_range: self.auto_collect_search_range,
+            auto_collect_max_cost: self.auto_collect_max_cost,
+            path_algorithm: PathAlgorithm::from_name(&self.path_algorithm).unwrap_or(PathAlgorithm::AStar),
+            path_failure: self.path_failure_policy(),
+            path_search: self.path_search_options(),
+            render_dir: self.render_dir.clone(),
+            tmp_focusing_pos: (0, 0),
+        }
+    }
+}
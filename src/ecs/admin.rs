@@ -0,0 +1,320 @@
+use super::*;
+use crate::ecs::types::{*};
+use crate::ecs::components::{*};
+use crate::ecs::flow_field::{FlowFieldResource};
+use crate::ecs::parcel::{LandParcel, LandParcelResource};
+use crate::ecs::building::{self, Building, BuildingKind, BuildingsResource};
+use legion::prelude::*;
+use serde::Serialize;
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct EntityInfo {
+    pub id: u32,
+    pub model: Option<u32>,
+    pub owner: Option<u32>,
+    pub land_pos: Option<(i32, i32)>,
+    pub home_land: Option<(i32, i32)>,
+    pub velocity: Option<(i32, i32)>,
+    pub follow_path: Option<((i32, i32), (i32, i32))>,
+    pub goal_step: Option<i32>,
+    pub goal_target: Option<(i32, i32)>,
+    pub action_duration_ms: Option<u32>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ChunkEntity {
+    pub id: u32,
+    pub model: u32,
+    pub land_pos: (i32, i32),
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ChunkInfo {
+    pub chunk_key: i32,
+    pub entities: Vec<ChunkEntity>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct WorldStats {
+    pub frame: u32,
+    pub entities_per_model: HashMap<u32, usize>,
+    pub chunks: usize,
+    pub cached_paths: usize,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PathCacheStats {
+    pub cached_paths: usize,
+    pub total_nodes: usize,
+    pub longest_path: usize,
+    pub flow_fields: usize,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TileInfo {
+    pub x: i32,
+    pub y: i32,
+    pub tile: u8,
+    pub land: bool,
+    pub road: bool,
+    pub alley: bool,
+    pub resource: bool,
+    pub can_move_to: bool,
+    pub move_cost: u32,
+    pub parcel: Option<u32>,
+    pub building: Option<u32>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct OwnerBuildings {
+    pub owner: u32,
+    /// Resources held by the finished storages
+    pub stock: u32,
+    pub buildings: Vec<Building>,
+}
+
+pub fn model_from_name(name: &str) -> Option<u32> {
+    match name {
+        "axie" => Some(UnitModelType::Axie as u32),
+        "chimera" => Some(UnitModelType::Chimera as u32),
+        "resource" => Some(BuildingModelType::ResourceNode as u32),
+        _ => None,
+    }
+}
+
+/// Looks up a live entity by its index, only entities with a LandPos are searched
+pub fn find_entity(world: &mut World, index: u32) -> Option<Entity> {
+    let query = <(Read<LandPos>)>::query();
+    for (entity, _) in query.iter_entities_mut(world) {
+        if entity.index() == index {
+            return Some(entity);
+        }
+    }
+    None
+}
+
+fn check_tile(resources: &Resources, x: i32, y: i32) -> Result<(), String> {
+    match resources.get::<TileMapResource>() {
+        Some(tm) => {
+            let tm = &tm.0;
+            if x < 1 || y < 1 || x >= tm.w as i32 - 1 || y >= tm.h as i32 - 1 {
+                return Err(format!("{},{} is outside the map", x, y));
+            }
+            if !tm.can_move_to(&(x, y)) {
+                return Err(format!("{},{} is not reachable", x, y));
+            }
+            Ok(())
+        },
+        None => Err("no tile map".to_string())
+    }
+}
+
+/// Drops the entity from the quadrant map, set_quadrant_data_hash_map
+/// adds it back next update if it still lives
+fn remove_from_quadrant(world: &mut World, resources: &mut Resources, entity: Entity) {
+    let key = match world.get_component::<QuadrantKey>(entity) {
+        Some(key) => key.0,
+        None => return,
+    };
+    if let Some(mut qd) = resources.get_mut::<QuadrantDataHashMapResource>() {
+        if let Some(chunk) = qd.0.get_mut(&key) {
+            for objs in chunk.values_mut() {
+                objs.retain(|(e, _)| *e != entity);
+            }
+        }
+    }
+    let _ = world.remove_component::<QuadrantKey>(entity);
+}
+
+pub fn inspect_entity(world: &mut World, index: u32) -> Option<EntityInfo> {
+    let entity = find_entity(world, index)?;
+    Some(EntityInfo {
+        id: index,
+        model: world.get_tag::<Model>(entity).map(|m| m.0),
+        owner: world.get_tag::<Owner>(entity).map(|o| o.0),
+        land_pos: world.get_component::<LandPos>(entity).map(|p| (p.0, p.1)),
+        home_land: world.get_component::<HomeLand>(entity).map(|h| (h.0 .0, h.0 .1)),
+        velocity: world.get_component::<Moving>(entity).map(|mv| (mv.vx, mv.vy)),
+        follow_path: world.get_component::<FollowPath>(entity).map(|fp| ((fp.sx, fp.sy), (fp.tx, fp.ty))),
+        goal_step: world.get_component::<GatherResourceGoal>(entity).map(|g| g.step),
+        goal_target: world.get_component::<GatherResourceGoal>(entity).map(|g| (g.target_pos.0, g.target_pos.1)),
+        action_duration_ms: world.get_component::<GAction>(entity).map(|a| a.duration_ms),
+    })
+}
+
+pub fn list_chunk(resources: &Resources, chunk_key: i32) -> ChunkInfo {
+    let mut entities = Vec::new();
+    if let Some(qd) = resources.get::<QuadrantDataHashMapResource>() {
+        if let Some(chunk) = qd.0.get(&chunk_key) {
+            for objs in chunk.values() {
+                for (entity, data) in objs.iter() {
+                    entities.push(ChunkEntity{id: entity.index(), model: data.model, land_pos: data.land_pos});
+                }
+            }
+        }
+    }
+    entities.sort_by_key(|e| e.id);
+    ChunkInfo { chunk_key, entities }
+}
+
+pub fn world_stats(world: &mut World, resources: &Resources) -> WorldStats {
+    let mut entities_per_model = HashMap::new();
+    let query = <(Read<LandPos>, Tagged<Model>)>::query();
+    for (_, model) in query.iter_mut(world) {
+        *entities_per_model.entry(model.0).or_insert(0) += 1;
+    }
+    WorldStats {
+        frame: resources.get::<GameConfigResource>().map_or(0, |conf| conf.number_of_updates),
+        entities_per_model,
+        chunks: resources.get::<QuadrantDataHashMapResource>().map_or(0, |qd| qd.0.len()),
+        cached_paths: resources.get::<PathwayHashMapResource>().map_or(0, |pw| pw.0.len()),
+    }
+}
+
+pub fn spawn(world: &mut World, resources: &Resources, model: u32, x: i32, y: i32, owner: u32) -> Result<u32, String> {
+    check_tile(resources, x, y)?;
+    let land_pos = LandPos(x, y);
+    let entities = if model == UnitModelType::Axie as u32 {
+        world.insert(
+            (Owner(owner), Model(model), AutoCollect),
+            vec![(land_pos, HomeLand(land_pos))]
+        )
+    } else if model == UnitModelType::Chimera as u32 {
+        world.insert(
+            (Model(model), Chimera),
+            vec![(land_pos, Vel(0, 0), ChimeraState{state: 0})]
+        )
+    } else if model == BuildingModelType::ResourceNode as u32 {
+        world.insert(
+            (Model(model), Static),
+            vec![(land_pos,)]
+        )
+    } else {
+        return Err(format!("can not spawn model {}", model));
+    };
+    Ok(entities[0].index())
+}
+
+pub fn despawn(world: &mut World, resources: &mut Resources, index: u32) -> Result<(), String> {
+    let entity = find_entity(world, index).ok_or_else(|| format!("entity {}", index))?;
+    remove_from_quadrant(world, resources, entity);
+    if let (Some(id), Some(mut buildings)) = (world.get_component::<BuildingId>(entity).map(|id| id.0), resources.get_mut::<BuildingsResource>()) {
+        buildings.0.remove(id);
+    }
+    world.delete(entity);
+    Ok(())
+}
+
+pub fn teleport(world: &mut World, resources: &mut Resources, index: u32, x: i32, y: i32) -> Result<(), String> {
+    check_tile(resources, x, y)?;
+    let entity = find_entity(world, index).ok_or_else(|| format!("entity {}", index))?;
+    remove_from_quadrant(world, resources, entity);
+    if let Some(mut pos) = world.get_component_mut::<LandPos>(entity) {
+        pos.0 = x;
+        pos.1 = y;
+    }
+    let _ = world.remove_component::<NewPos>(entity);
+    let _ = world.remove_component::<Moving>(entity);
+    let _ = world.remove_component::<FollowPath>(entity);
+    let _ = world.remove_component::<PathCursor>(entity);
+    // the unit did not arrive anywhere, its action and goal are dropped with the path
+    let _ = world.remove_component::<GAction>(entity);
+    let _ = world.remove_tag::<GActionGatherResource>(entity);
+    let _ = world.remove_tag::<GActionReleaseResource>(entity);
+    let _ = world.remove_component::<GatherResourceGoal>(entity);
+    let _ = world.remove_tag::<GGoal>(entity);
+    let _ = world.remove_component::<PathFailed>(entity);
+    Ok(())
+}
+
+pub fn path_cache_stats(resources: &Resources) -> PathCacheStats {
+    let mut stats = PathCacheStats { cached_paths: 0, total_nodes: 0, longest_path: 0, flow_fields: 0 };
+    if let Some(pw) = resources.get::<PathwayHashMapResource>() {
+        for paths in pw.0.values() {
+            stats.cached_paths += 1;
+            stats.total_nodes += paths.len();
+            stats.longest_path = stats.longest_path.max(paths.len());
+        }
+    }
+    stats.flow_fields = resources.get::<FlowFieldResource>().map_or(0, |flow| flow.0.len());
+    stats
+}
+
+pub fn tile_info(resources: &Resources, x: i32, y: i32) -> Option<TileInfo> {
+    let tm = resources.get::<TileMapResource>()?;
+    let tm = &tm.0;
+    if x < 0 || y < 0 || x >= tm.w as i32 || y >= tm.h as i32 {
+        return None;
+    }
+    let pos = (x, y);
+    let inside = x >= 1 && y >= 1 && x < tm.w as i32 - 1 && y < tm.h as i32 - 1;
+    Some(TileInfo {
+        x,
+        y,
+        tile: tm.data[y as usize][x as usize],
+        land: tm.is_land_tile(&pos),
+        road: tm.is_road_tile(&pos),
+        alley: tm.is_alley_tile(&pos),
+        resource: tm.is_resource_tile(&pos),
+        can_move_to: inside && tm.can_move_to(&pos),
+        move_cost: tm.get_move_cost(&pos),
+        parcel: resources.get::<LandParcelResource>().and_then(|parcels| parcels.0.at(&pos).map(|p| p.id)),
+        building: resources.get::<BuildingsResource>().and_then(|buildings| buildings.0.at(&pos).map(|b| b.id)),
+    })
+}
+
+pub fn parcel_info(resources: &Resources, x: i32, y: i32) -> Option<LandParcel> {
+    let parcels = resources.get::<LandParcelResource>()?;
+    parcels.0.at(&(x, y)).cloned()
+}
+
+pub fn owner_parcels(resources: &Resources, owner: u32) -> Vec<LandParcel> {
+    resources.get::<LandParcelResource>()
+        .map_or(Vec::new(), |parcels| parcels.0.owned_by(owner).into_iter().cloned().collect())
+}
+
+/// Starts a building of `owner` with its top left tile at (x, y), paid from
+/// the owner's storages, and announces it. Returns the building id.
+pub fn place_building(world: &mut World, resources: &Resources, kind: BuildingKind, owner: u32, x: i32, y: i32) -> Result<u32, String> {
+    let tm = resources.get::<TileMapResource>().ok_or_else(|| "no tile map".to_string())?;
+    let parcels = resources.get::<LandParcelResource>().ok_or_else(|| "no land parcels".to_string())?;
+    let mut buildings = resources.get_mut::<BuildingsResource>().ok_or_else(|| "no buildings".to_string())?;
+    let id = buildings.0.place(&tm.0, &parcels.0, kind, owner, x, y)?;
+    let placed = buildings.0.get(id).cloned().ok_or_else(|| format!("building {}", id))?;
+    building::insert_entity(world, &placed);
+
+    let (frame, fixed_time_ms) = resources.get::<GameConfigResource>()
+        .map_or((0, 1), |conf| (conf.number_of_updates, conf.fixed_time_ms.max(1)));
+    let build_frames = (placed.build_ms as u64 + fixed_time_ms - 1) / fixed_time_ms;
+    if let Some(mut p) = resources.get_mut::<EmitEventResource>() {
+        p.0.push((LandPos(x, y).get_hash_map_key(), LunaciaWorldEvent::EventBuildingPlaced{
+            frame: frame,
+            id: id,
+            owner: owner,
+            model: kind.model(),
+            tx: x,
+            ty: y,
+            w: placed.w,
+            h: placed.h,
+            done_frame: frame + build_frames as u32,
+        }));
+    }
+    Ok(id)
+}
+
+pub fn building_info(resources: &Resources, id: u32) -> Option<Building> {
+    let buildings = resources.get::<BuildingsResource>()?;
+    buildings.0.get(id).cloned()
+}
+
+pub fn owner_buildings(resources: &Resources, owner: u32) -> OwnerBuildings {
+    match resources.get::<BuildingsResource>() {
+        Some(buildings) => OwnerBuildings {
+            owner,
+            stock: buildings.0.stock(owner),
+            buildings: buildings.0.owned_by(owner).into_iter().cloned().collect(),
+        },
+        None => OwnerBuildings { owner, stock: 0, buildings: Vec::new() },
+    }
+}
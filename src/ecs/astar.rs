@@ -38,12 +38,61 @@ fn reverse_path<N: Eq + Hash>(mut parents: HashMap<N, N>, start: N) -> Vec<N> {
     path.into_iter().rev().collect()
 }
 
-pub fn astar<N, C, FN, IN, FH, FS>(
+/// Limits of `astar_with`, the default has none
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SearchOptions<C> {
+    /// Gives up after expanding this many nodes
+    pub max_expanded: Option<usize>,
+    /// Ignores the paths costing more
+    pub max_cost: Option<C>,
+    /// Between nodes of the same estimate, expands first the one closest to the goal
+    pub tie_break: bool,
+}
+
+impl<C> Default for SearchOptions<C> {
+    fn default() -> Self {
+        SearchOptions { max_expanded: None, max_cost: None, tie_break: false }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SearchOutcome {
+    Found,
+    /// Every node in reach was expanded
+    Unreachable,
+    /// Cut off by `max_expanded`
+    ExpansionLimit,
+    /// Nothing left within `max_cost`
+    CostLimit,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SearchStats {
+    pub expanded: usize,
+    /// Nodes pushed to the open list, counting the ones pushed again for a better cost
+    pub pushed: usize,
+    pub max_open: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct SearchResult<N, C> {
+    pub outcome: SearchOutcome,
+    /// Path to the goal and its cost when found
+    pub path: Option<(Vec<N>, C)>,
+    /// Otherwise the path to the expanded node with the lowest heuristic, the cheapest
+    /// one on ties. None when that is the start.
+    pub partial: Option<(Vec<N>, C)>,
+    pub stats: SearchStats,
+}
+
+/// `astar` within `options`, reporting how much work the search did
+pub fn astar_with<N, C, FN, IN, FH, FS>(
     start: &N,
     neighbours: FN,
     heuristic: FH,
     success: FS,
-) -> Option<(Vec<N>, C)>
+    options: &SearchOptions<C>,
+) -> SearchResult<N, C>
 where
     N: Eq + Hash + Clone,
     C: Zero + Ord + Copy,
@@ -52,16 +101,30 @@ where
     FH: Fn(&N) -> C,
     FS: Fn(&N) -> bool,
 {
+    let mut stats = SearchStats::default();
     let mut to_see = BinaryHeap::new();
+    let start_h = heuristic(start);
     to_see.push(InvCmpHolder {
-        key: heuristic(start),
+        key: (start_h, if options.tie_break { start_h } else { Zero::zero() }),
         payload: (Zero::zero(), start.clone()),
     });
+    stats.pushed += 1;
     let mut parents: HashMap<N, (N, C)> = HashMap::new();
+    // expanded node closest to the goal: heuristic, cost, node
+    let mut best: (C, C, N) = (start_h, Zero::zero(), start.clone());
+    let mut cost_limited = false;
+    let mut outcome = SearchOutcome::Unreachable;
+
     while let Some(InvCmpHolder { payload: (cost, node), .. }) = to_see.pop() {
         if success(&node) {
             let parents = parents.into_iter().map(|(n, (p, _))| (n, p)).collect();
-            return Some((reverse_path(parents, node), cost));
+            stats.max_open = stats.max_open.max(to_see.len() + 1);
+            return SearchResult {
+                outcome: SearchOutcome::Found,
+                path: Some((reverse_path(parents, node), cost)),
+                partial: None,
+                stats,
+            };
         }
         // We may have inserted a node several time into the binary heap if we found
         // a better way to access it. Ensure that we are currently dealing with the
@@ -71,8 +134,21 @@ where
                 continue;
             }
         }
+        if options.max_expanded.map_or(false, |max| stats.expanded >= max) {
+            outcome = SearchOutcome::ExpansionLimit;
+            break;
+        }
+        stats.expanded += 1;
+        let h = heuristic(&node);
+        if (h, cost) < (best.0, best.1) {
+            best = (h, cost, node.clone());
+        }
         for (neighbour, move_cost) in neighbours(&node) {
             let new_cost = cost + move_cost;
+            if options.max_cost.map_or(false, |max| new_cost > max) {
+                cost_limited = true;
+                continue;
+            }
             if neighbour != *start {
                 let mut inserted = true;
                 match parents.entry(neighbour.clone()) {
@@ -88,14 +164,105 @@ where
                     }
                 };
                 if inserted {
-                    let new_predicted_cost = new_cost + heuristic(&neighbour);
+                    let neighbour_h = heuristic(&neighbour);
                     to_see.push(InvCmpHolder {
-                        key: new_predicted_cost,
+                        key: (new_cost + neighbour_h, if options.tie_break { neighbour_h } else { Zero::zero() }),
                         payload: (new_cost, neighbour),
                     });
+                    stats.pushed += 1;
+                    stats.max_open = stats.max_open.max(to_see.len());
                 }
             }
         }
     }
-    None
+
+    if outcome == SearchOutcome::Unreachable && cost_limited {
+        outcome = SearchOutcome::CostLimit;
+    }
+    let (_, best_cost, best_node) = best;
+    let partial = if best_node == *start {
+        None
+    } else {
+        let parents = parents.into_iter().map(|(n, (p, _))| (n, p)).collect();
+        Some((reverse_path(parents, best_node), best_cost))
+    };
+    SearchResult { outcome, path: None, partial, stats }
+}
+
+pub fn astar<N, C, FN, IN, FH, FS>(
+    start: &N,
+    neighbours: FN,
+    heuristic: FH,
+    success: FS,
+) -> Option<(Vec<N>, C)>
+where
+    N: Eq + Hash + Clone,
+    C: Zero + Ord + Copy,
+    FN: Fn(&N) -> IN,
+    IN: IntoIterator<Item = (N, C)>,
+    FH: Fn(&N) -> C,
+    FS: Fn(&N) -> bool,
+{
+    astar_with(start, neighbours, heuristic, success, &SearchOptions::default()).path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Nodes 0 to 10 on a line, one step costs 1
+    fn line_search(goal: i32, options: &SearchOptions<u32>) -> SearchResult<i32, u32> {
+        astar_with(
+            &0,
+            |&n| vec![n - 1, n + 1].into_iter().filter(|m| *m >= 0 && *m <= 10).map(|m| (m, 1)),
+            |&n| (goal - n).abs() as u32,
+            |&n| n == goal,
+            options,
+        )
+    }
+
+    #[test]
+    fn counts_the_nodes_it_expands_and_pushes() {
+        let search = line_search(5, &SearchOptions::default());
+        assert_eq!(search.outcome, SearchOutcome::Found);
+        assert_eq!(search.path, Some(((0..=5).collect(), 5)));
+        assert_eq!(search.partial, None);
+        assert_eq!(search.stats, SearchStats { expanded: 5, pushed: 6, max_open: 1 });
+    }
+
+    #[test]
+    fn stops_at_max_expanded_with_the_path_to_the_best_node() {
+        let options = SearchOptions { max_expanded: Some(3), ..SearchOptions::default() };
+        let search = line_search(9, &options);
+        assert_eq!(search.outcome, SearchOutcome::ExpansionLimit);
+        assert_eq!(search.stats.expanded, 3);
+        assert_eq!(search.path, None);
+        assert_eq!(search.partial, Some((vec![0, 1, 2], 2)));
+    }
+
+    #[test]
+    fn leaves_out_paths_over_max_cost() {
+        let options = SearchOptions { max_cost: Some(3), ..SearchOptions::default() };
+        let search = line_search(9, &options);
+        assert_eq!(search.outcome, SearchOutcome::CostLimit);
+        assert_eq!(search.stats.expanded, 4);
+        assert_eq!(search.partial, Some((vec![0, 1, 2, 3], 3)));
+
+        // a path within the limit is still found
+        let options = SearchOptions { max_cost: Some(9), ..SearchOptions::default() };
+        assert_eq!(line_search(9, &options).path.map(|(_, cost)| cost), Some(9));
+    }
+
+    #[test]
+    fn expands_everything_in_reach_when_unreachable() {
+        let search = line_search(20, &SearchOptions::default());
+        assert_eq!(search.outcome, SearchOutcome::Unreachable);
+        assert_eq!(search.stats.expanded, 11);
+        assert_eq!(search.partial, Some(((0..=10).collect(), 10)));
+
+        // nothing is closer than the start
+        let search = line_search(-5, &SearchOptions::default());
+        assert_eq!(search.outcome, SearchOutcome::Unreachable);
+        assert_eq!(search.partial, None);
+    }
 }
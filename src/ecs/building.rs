@@ -0,0 +1,337 @@
+use legion::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::ecs::components::{BuildingId, BuildingModelType, LandPos, Model, Owner, Static};
+use crate::ecs::parcel::{LandParcels};
+use crate::ecs::submap::{TileMap};
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BuildingKind {
+    /// Takes the resources delivered on its parcel, pays for other buildings
+    Storage,
+    House,
+}
+
+impl BuildingKind {
+    pub fn from_name(name: &str) -> Option<BuildingKind> {
+        match name {
+            "storage" => Some(BuildingKind::Storage),
+            "house" => Some(BuildingKind::House),
+            _ => None,
+        }
+    }
+
+    pub fn model(&self) -> u32 {
+        match self {
+            BuildingKind::Storage => BuildingModelType::Storage as u32,
+            BuildingKind::House => BuildingModelType::House as u32,
+        }
+    }
+
+    /// Width and height in tiles
+    pub fn footprint(&self) -> (i32, i32) {
+        match self {
+            BuildingKind::Storage => (1, 1),
+            BuildingKind::House => (2, 2),
+        }
+    }
+
+    /// Simulated time to finish construction
+    pub fn build_ms(&self) -> u32 {
+        match self {
+            BuildingKind::Storage => 20000,
+            BuildingKind::House => 30000,
+        }
+    }
+
+    /// Resources taken from the owner's storages when placed
+    pub fn cost(&self) -> u32 {
+        match self {
+            BuildingKind::Storage => 0,
+            BuildingKind::House => 10,
+        }
+    }
+
+    pub fn capacity(&self) -> u32 {
+        match self {
+            BuildingKind::Storage => 50,
+            BuildingKind::House => 0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Building {
+    pub id: u32,
+    pub kind: BuildingKind,
+    pub owner: u32,
+    pub parcel: u32,
+    /// Top left tile of the footprint
+    pub x: i32,
+    pub y: i32,
+    pub w: i32,
+    pub h: i32,
+    pub built_ms: u32,
+    pub build_ms: u32,
+    pub stored: u32,
+    pub capacity: u32,
+}
+
+impl Building {
+    pub fn is_done(&self) -> bool {
+        self.built_ms >= self.build_ms
+    }
+
+    pub fn tiles(&self) -> Vec<(i32, i32)> {
+        footprint_tiles(self.x, self.y, self.w, self.h)
+    }
+}
+
+fn footprint_tiles(x: i32, y: i32, w: i32, h: i32) -> Vec<(i32, i32)> {
+    let mut tiles = Vec::new();
+    for ty in y..y + h {
+        for tx in x..x + w {
+            tiles.push((tx, ty));
+        }
+    }
+    tiles
+}
+
+/// Parcel covering all of `tiles`, when it belongs to `owner`
+fn owned_parcel(parcels: &LandParcels, owner: u32, tiles: &[(i32, i32)]) -> Option<u32> {
+    let mut parcel = None;
+    for pos in tiles {
+        let id = parcels.at(pos)?.id;
+        if *parcel.get_or_insert(id) != id {
+            return None;
+        }
+    }
+    let parcel = parcel?;
+    match parcels.get(parcel).and_then(|p| p.owner) {
+        Some(o) if o == owner => Some(parcel),
+        _ => None,
+    }
+}
+
+/// Every building of the map and the building standing on each tile
+#[derive(Clone, Debug, Default)]
+pub struct Buildings {
+    by_tile: HashMap<(i32, i32), u32>,
+    buildings: BTreeMap<u32, Building>,
+    next_id: u32,
+}
+
+impl Buildings {
+    pub fn new() -> Self {
+        Buildings::default()
+    }
+
+    /// Parcel a building of `kind` would stand on with its top left tile at (x, y).
+    /// The footprint has to cover free land tiles of a single parcel of `owner`.
+    pub fn check_placement(&self, tm: &TileMap, parcels: &LandParcels, kind: BuildingKind, owner: u32, x: i32, y: i32) -> Result<u32, String> {
+        let (w, h) = kind.footprint();
+        let mut parcel = None;
+        for pos in footprint_tiles(x, y, w, h) {
+            if pos.0 < 0 || pos.1 < 0 || pos.0 >= tm.w as i32 || pos.1 >= tm.h as i32 {
+                return Err(format!("{},{} is outside the map", pos.0, pos.1));
+            }
+            if !tm.is_land_tile(&pos) {
+                return Err(format!("{},{} is not a land tile", pos.0, pos.1));
+            }
+            if let Some(id) = self.by_tile.get(&pos) {
+                return Err(format!("{},{} is taken by building {}", pos.0, pos.1, id));
+            }
+            let id = parcels.at(&pos).map(|p| p.id).ok_or_else(|| format!("{},{} has no parcel", pos.0, pos.1))?;
+            if *parcel.get_or_insert(id) != id {
+                return Err(format!("{}x{} at {},{} crosses parcels", w, h, x, y));
+            }
+        }
+        let parcel = parcel.ok_or_else(|| "empty footprint".to_string())?;
+        match parcels.get(parcel).and_then(|p| p.owner) {
+            Some(o) if o == owner => Ok(parcel),
+            Some(o) => Err(format!("parcel {} belongs to {}", parcel, o)),
+            None => Err(format!("parcel {} has no owner", parcel)),
+        }
+    }
+
+    /// Starts the construction of a building once its cost is paid, returns its id
+    pub fn place(&mut self, tm: &TileMap, parcels: &LandParcels, kind: BuildingKind, owner: u32, x: i32, y: i32) -> Result<u32, String> {
+        let parcel = self.check_placement(tm, parcels, kind, owner, x, y)?;
+        let stock = self.stock(owner);
+        if stock < kind.cost() {
+            return Err(format!("costs {} resources, owner {} has {}", kind.cost(), owner, stock));
+        }
+        self.withdraw(owner, kind.cost());
+        Ok(self.insert(kind, owner, parcel, x, y, 0))
+    }
+
+    /// Adds a finished building holding `stored` resources without paying for it,
+    /// for the buildings standing when the world starts
+    pub fn place_built(&mut self, tm: &TileMap, parcels: &LandParcels, kind: BuildingKind, owner: u32, x: i32, y: i32, stored: u32) -> Result<u32, String> {
+        let parcel = self.check_placement(tm, parcels, kind, owner, x, y)?;
+        if stored > kind.capacity() {
+            return Err(format!("holds at most {} resources", kind.capacity()));
+        }
+        let id = self.insert(kind, owner, parcel, x, y, kind.build_ms());
+        if let Some(building) = self.buildings.get_mut(&id) {
+            building.stored = stored;
+        }
+        Ok(id)
+    }
+
+    fn insert(&mut self, kind: BuildingKind, owner: u32, parcel: u32, x: i32, y: i32, built_ms: u32) -> u32 {
+        let (w, h) = kind.footprint();
+        let id = self.next_id;
+        self.next_id += 1;
+        let building = Building { id, kind, owner, parcel, x, y, w, h, built_ms, build_ms: kind.build_ms(), stored: 0, capacity: kind.capacity() };
+        for pos in building.tiles() {
+            self.by_tile.insert(pos, id);
+        }
+        self.buildings.insert(id, building);
+        id
+    }
+
+    pub fn remove(&mut self, id: u32) -> Option<Building> {
+        let building = self.buildings.remove(&id)?;
+        for pos in building.tiles() {
+            self.by_tile.remove(&pos);
+        }
+        Some(building)
+    }
+
+    /// Follows the parcels after they were built again. A building whose footprint
+    /// is no longer on a single parcel of its owner is torn down, those are returned.
+    pub fn reparcel(&mut self, parcels: &LandParcels) -> Vec<Building> {
+        let mut broken = Vec::new();
+        for building in self.buildings.values_mut() {
+            match owned_parcel(parcels, building.owner, &building.tiles()) {
+                Some(parcel) => building.parcel = parcel,
+                None => broken.push(building.id),
+            }
+        }
+        broken.into_iter().filter_map(|id| self.remove(id)).collect()
+    }
+
+    /// Moves construction forward by `ms`, returns the buildings finished by it
+    pub fn advance(&mut self, ms: u32) -> Vec<u32> {
+        let mut done = Vec::new();
+        for building in self.buildings.values_mut().filter(|b| !b.is_done()) {
+            building.built_ms = (building.built_ms + ms).min(building.build_ms);
+            if building.is_done() {
+                done.push(building.id);
+            }
+        }
+        done
+    }
+
+    /// Resources held by the finished storages of `owner`
+    pub fn stock(&self, owner: u32) -> u32 {
+        self.buildings.values().filter(|b| b.owner == owner && b.is_done()).map(|b| b.stored).sum()
+    }
+
+    /// Takes `amount` out of the storages of `owner`, lowest ids first
+    fn withdraw(&mut self, owner: u32, mut amount: u32) {
+        for building in self.buildings.values_mut().filter(|b| b.owner == owner && b.is_done()) {
+            let taken = building.stored.min(amount);
+            building.stored -= taken;
+            amount -= taken;
+        }
+    }
+
+    /// Stores `amount` in a finished storage of `owner` with room on the parcel
+    /// of `pos`. Returns the building that took it, none refuses the delivery.
+    pub fn deliver(&mut self, parcels: &LandParcels, owner: u32, pos: &(i32, i32), amount: u32) -> Option<u32> {
+        let parcel = parcels.at(pos)?.id;
+        let building = self.buildings.values_mut()
+            .find(|b| b.owner == owner && b.parcel == parcel && b.is_done() && b.stored + amount <= b.capacity)?;
+        building.stored += amount;
+        Some(building.id)
+    }
+
+    pub fn get(&self, id: u32) -> Option<&Building> {
+        self.buildings.get(&id)
+    }
+
+    /// Building standing on a tile
+    pub fn at(&self, pos: &(i32, i32)) -> Option<&Building> {
+        self.by_tile.get(pos).and_then(|id| self.buildings.get(id))
+    }
+
+    pub fn owned_by(&self, owner: u32) -> Vec<&Building> {
+        self.buildings.values().filter(|b| b.owner == owner).collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Building> {
+        self.buildings.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.buildings.len()
+    }
+}
+
+/// Static entity standing for a building, at the top left tile of its footprint
+pub fn insert_entity(world: &mut World, building: &Building) -> Entity {
+    let entities = world.insert(
+        (Model(building.kind.model()), Owner(building.owner), Static),
+        vec![(LandPos(building.x, building.y), BuildingId(building.id))]
+    );
+    entities[0]
+}
+
+pub struct BuildingsResource(pub Buildings);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reparcel_tears_down_buildings_off_their_owners_parcel() {
+        let mut tm = TileMap::from_rows(&[
+            ".......",
+            ".RRRRR.",
+            ".Rllll.",
+            ".Rllll.",
+            ".RRRRR.",
+            ".......",
+        ]);
+        let mut parcels = LandParcels::build(&tm);
+        let parcel = parcels.at(&(2, 2)).unwrap().id;
+        parcels.set_owner(parcel, Some(1)).unwrap();
+        let mut buildings = Buildings::new();
+        let storage = buildings.place_built(&tm, &parcels, BuildingKind::Storage, 1, 2, 3, 0).unwrap();
+        let house = buildings.place_built(&tm, &parcels, BuildingKind::House, 1, 4, 2, 0).unwrap();
+        assert!(buildings.reparcel(&parcels).is_empty());
+
+        // a road across the parcel leaves the house on a new parcel nobody owns
+        tm.data[2][3] = 6;
+        tm.data[3][3] = 6;
+        parcels.rebuild(&tm);
+        let removed: Vec<u32> = buildings.reparcel(&parcels).iter().map(|b| b.id).collect();
+        assert_eq!(removed, vec![house]);
+        assert!(buildings.at(&(4, 2)).is_none());
+        assert_eq!(buildings.get(storage).map(|b| b.parcel), Some(parcel));
+    }
+
+    #[test]
+    fn deliver_needs_room_in_a_finished_storage() {
+        let tm = TileMap::from_rows(&[
+            "....",
+            ".Rl.",
+            "....",
+        ]);
+        let mut parcels = LandParcels::build(&tm);
+        let parcel = parcels.at(&(2, 1)).unwrap().id;
+        parcels.set_owner(parcel, Some(1)).unwrap();
+        let mut buildings = Buildings::new();
+        let capacity = BuildingKind::Storage.capacity();
+        let storage = buildings.place_built(&tm, &parcels, BuildingKind::Storage, 1, 2, 1, capacity - 1).unwrap();
+
+        assert_eq!(buildings.deliver(&parcels, 2, &(2, 1), 1), None);
+        assert_eq!(buildings.deliver(&parcels, 1, &(2, 1), 1), Some(storage));
+        assert_eq!(buildings.deliver(&parcels, 1, &(2, 1), 1), None);
+        assert_eq!(buildings.stock(1), capacity);
+    }
+}
@@ -1,3 +1,4 @@
+use crate::ecs::pathing::{SharedPath};
 
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -63,11 +64,17 @@ pub struct Moving{
     pub cost: u32,
     pub step: u64,
     pub maxstep: u64,
+    pub seg_steps: u32, // steps left in the last announced move segment
 }
 
 impl Moving {
     pub fn new() -> Self {
-        Moving {vx: 0, vy: 0, speed: 2, cost: 1, step: 0, maxstep: 0}
+        Moving {vx: 0, vy: 0, speed: 2, cost: 1, step: 0, maxstep: 0, seg_steps: 0}
+    }
+
+    /// Progress added to `step` by one update of `fixed_time_ms`
+    pub fn step_per_update(&self, fixed_time_ms: u64) -> u64 {
+        self.speed * fixed_time_ms / self.cost as u64
     }
 }
 
@@ -95,8 +102,14 @@ pub struct AutoCollect;
 pub enum BuildingModelType {
     None = 1000,
     ResourceNode,
+    Storage,
+    House,
 }
 
+/// Entity of an entry of `Buildings`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BuildingId(pub u32);
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct ResourceNode;
 
@@ -118,14 +131,55 @@ pub struct FollowPath {
     pub sy: i32,
     pub tx: i32,
     pub ty: i32,
+    /// Failed searches retried so far
+    pub attempts: u32,
+    /// No search before this frame
+    pub retry_frame: u32,
+}
+
+impl FollowPath {
+    pub fn new(sx: i32, sy: i32, tx: i32, ty: i32) -> Self {
+        FollowPath { sx, sy, tx, ty, attempts: 0, retry_frame: 0 }
+    }
+}
+
+/// Path a unit walks, `index` is the tile it stands on once its current step is done
+#[derive(Clone, Debug, PartialEq)]
+pub struct PathCursor {
+    pub path: SharedPath,
+    pub index: usize,
+}
+
+impl PathCursor {
+    pub fn new(path: SharedPath) -> Self {
+        PathCursor { path, index: 0 }
+    }
+
+    pub fn current(&self) -> Option<(i32, i32)> {
+        self.path.get(self.index).cloned()
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.index + 1 >= self.path.len()
+    }
+
+    /// Tiles left to walk, starting with the current one
+    pub fn remaining(&self) -> &[(i32, i32)] {
+        &self.path[self.index.min(self.path.len())..]
+    }
 }
 
+/// No path reached the target of the unit. Its goal is dropped once it stands still.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PathFailed;
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct ChimeraSpawner {
-    // x: i32,
-    // y: i32,
-    // w: i32,
-    // h: i32,
+    /// Area chimeras appear in, the spawner position when empty
+    pub x: i32,
+    pub y: i32,
+    pub w: i32,
+    pub h: i32,
     pub count: i32,
     pub cooldown_ms: i32,
     pub tick_ms: i32,
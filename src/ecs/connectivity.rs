@@ -0,0 +1,180 @@
+use std::collections::VecDeque;
+
+use crate::ecs::submap::{TileMap};
+
+const NO_REGION: u32 = u32::MAX;
+
+/// Passable tiles labelled by the connected region they belong to, following the
+/// steps of `TileMap::successors`. Those steps go both ways between passable tiles,
+/// so two tiles with the same label reach each other.
+#[derive(Clone, Debug)]
+pub struct Connectivity {
+    w: usize,
+    h: usize,
+    labels: Vec<u32>,
+    /// Next free label, labels of relabelled regions are not reused
+    next_label: u32,
+}
+
+impl Connectivity {
+    pub fn build(tm: &TileMap) -> Self {
+        let mut conn = Connectivity { w: tm.w, h: tm.h, labels: vec![NO_REGION; tm.w * tm.h], next_label: 0 };
+        for y in 0..tm.h as i32 {
+            for x in 0..tm.w as i32 {
+                conn.flood(tm, (x, y));
+            }
+        }
+        conn
+    }
+
+    fn index(&self, pos: &(i32, i32)) -> Option<usize> {
+        if pos.0 < 0 || pos.1 < 0 || pos.0 as usize >= self.w || pos.1 as usize >= self.h {
+            return None;
+        }
+        Some(pos.1 as usize * self.w + pos.0 as usize)
+    }
+
+    /// Region of a passable tile
+    pub fn region(&self, pos: &(i32, i32)) -> Option<u32> {
+        self.index(pos).map(|i| self.labels[i]).filter(|&l| l != NO_REGION)
+    }
+
+    /// A path from `from` ends on `to`. Searches may end on an empty tile next
+    /// to the region, like a resource node, so those look at their neighbours.
+    pub fn reachable(&self, from: &(i32, i32), to: &(i32, i32)) -> bool {
+        let region = match self.region(from) {
+            Some(region) => region,
+            None => return from == to,
+        };
+        if let Some(target) = self.region(to) {
+            return target == region;
+        }
+        if self.index(to).is_none() {
+            return false;
+        }
+        // diagonal steps never end on an empty tile
+        [(-1, 0), (1, 0), (0, -1), (0, 1)].iter()
+            .any(|&(dx, dy)| self.region(&(to.0 + dx, to.1 + dy)) == Some(region))
+    }
+
+    /// Labels the region of `start` if it is passable and not labelled yet
+    fn flood(&mut self, tm: &TileMap, start: (i32, i32)) {
+        let i = match self.index(&start) {
+            Some(i) => i,
+            None => return,
+        };
+        if self.labels[i] != NO_REGION || !tm.is_passable(&start) {
+            return;
+        }
+        let label = self.next_label;
+        self.next_label += 1;
+        self.labels[i] = label;
+        let mut open = VecDeque::new();
+        open.push_back(start);
+        while let Some(pos) = open.pop_front() {
+            for (next, _) in tm.successors(&pos) {
+                if !tm.is_passable(&next) {
+                    continue;
+                }
+                if let Some(j) = self.index(&next) {
+                    if self.labels[j] == NO_REGION {
+                        self.labels[j] = label;
+                        open.push_back(next);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Labels again the regions around a tile that changed. Each one touching it
+    /// or its neighbours is flooded again, which merges and splits them as needed.
+    /// Returns how many regions were labelled.
+    pub fn update(&mut self, tm: &TileMap, pos: &(i32, i32)) -> u32 {
+        let mut around = Vec::new();
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                around.push((pos.0 + dx, pos.1 + dy));
+            }
+        }
+        let mut stale: Vec<u32> = around.iter().filter_map(|p| self.region(p)).collect();
+        stale.sort();
+        stale.dedup();
+        if !stale.is_empty() {
+            for label in self.labels.iter_mut() {
+                if stale.binary_search(label).is_ok() {
+                    *label = NO_REGION;
+                }
+            }
+        }
+        let first = self.next_label;
+        for p in around {
+            self.flood(tm, p);
+        }
+        self.next_label - first
+    }
+}
+
+pub struct ConnectivityResource(pub Connectivity);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every pair of tiles is connected in `conn` exactly when it is in a fresh build
+    fn assert_matches_build(conn: &Connectivity, tm: &TileMap) {
+        let fresh = Connectivity::build(tm);
+        let tiles: Vec<(i32, i32)> = (0..tm.h as i32).flat_map(|y| (0..tm.w as i32).map(move |x| (x, y))).collect();
+        for a in tiles.iter() {
+            assert_eq!(conn.region(a).is_some(), fresh.region(a).is_some(), "{:?}", a);
+            for b in tiles.iter() {
+                if let (Some(_), Some(_)) = (fresh.region(a), fresh.region(b)) {
+                    assert_eq!(conn.region(a) == conn.region(b), fresh.region(a) == fresh.region(b), "{:?} {:?}", a, b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn update_splits_and_merges() {
+        let mut tm = TileMap::from_rows(&[
+            ".........",
+            ".RRRRRRR.",
+            ".l.....l.",
+            ".........",
+        ]);
+        let mut conn = Connectivity::build(&tm);
+        assert_eq!(conn.region(&(1, 2)), conn.region(&(7, 2)));
+
+        // clearing the middle of the road cuts it in two
+        tm.data[1][4] = 0;
+        conn.update(&tm, &(4, 1));
+        assert_eq!(conn.region(&(4, 1)), None);
+        assert_ne!(conn.region(&(1, 2)), conn.region(&(7, 2)));
+        assert!(!conn.reachable(&(1, 2), &(7, 2)));
+        assert_matches_build(&conn, &tm);
+
+        // an alley joins both halves again
+        tm.data[1][4] = 11;
+        conn.update(&tm, &(4, 1));
+        assert_eq!(conn.region(&(1, 2)), conn.region(&(7, 2)));
+        assert!(conn.reachable(&(1, 2), &(7, 2)));
+        assert_matches_build(&conn, &tm);
+    }
+
+    #[test]
+    fn update_keeps_unrelated_regions() {
+        let mut tm = TileMap::from_rows(&[
+            ".......",
+            ".RRR.R.",
+            ".......",
+        ]);
+        let mut conn = Connectivity::build(&tm);
+        let far = conn.region(&(5, 1));
+        assert_ne!(conn.region(&(1, 1)), far);
+
+        tm.data[1][2] = 0;
+        conn.update(&tm, &(2, 1));
+        assert_eq!(conn.region(&(5, 1)), far);
+        assert_matches_build(&conn, &tm);
+    }
+}
@@ -0,0 +1,216 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+use crate::ecs::submap::{TileMap};
+
+const UNREACHABLE: u32 = u32::MAX;
+
+/// Steps traced ahead of a unit, the longest move segment a field announces
+pub const FLOW_TRACE_STEPS: usize = 32;
+
+/// Cost to reach `goal` from every tile of the map, the same cost
+/// `astar` minimises. A unit walks it by stepping to its cheapest successor.
+#[derive(Clone, Debug)]
+pub struct FlowField {
+    pub goal: (i32, i32),
+    w: usize,
+    h: usize,
+    cost: Vec<u32>,
+    /// Frame a unit last asked for the field
+    pub last_used: u32,
+}
+
+impl FlowField {
+    /// Dijkstra from the goal over the reversed steps of `TileMap::successors`,
+    /// returns the field and the number of expanded tiles
+    pub fn build(tm: &TileMap, goal: (i32, i32)) -> (FlowField, usize) {
+        let mut field = FlowField { goal, w: tm.w, h: tm.h, cost: vec![UNREACHABLE; tm.w * tm.h], last_used: 0 };
+        let mut expanded = 0;
+        let mut open = BinaryHeap::new();
+        if let Some(i) = field.index(&goal) {
+            field.cost[i] = 0;
+            open.push(Reverse((0, goal)));
+        }
+        while let Some(Reverse((cost, pos))) = open.pop() {
+            if cost > field.cost_at(&pos) {
+                continue;
+            }
+            expanded += 1;
+            // like in `successors`, nothing leaves an empty tile
+            if pos != goal && !tm.is_passable(&pos) {
+                continue;
+            }
+            for from in predecessors(tm, &pos) {
+                let next = cost + tm.step_cost(&from, &pos);
+                let i = field.index(&from).unwrap();
+                if next < field.cost[i] {
+                    field.cost[i] = next;
+                    open.push(Reverse((next, from)));
+                }
+            }
+        }
+        (field, expanded)
+    }
+
+    fn index(&self, pos: &(i32, i32)) -> Option<usize> {
+        if pos.0 < 0 || pos.1 < 0 || pos.0 as usize >= self.w || pos.1 as usize >= self.h {
+            return None;
+        }
+        Some(pos.1 as usize * self.w + pos.0 as usize)
+    }
+
+    fn cost_at(&self, pos: &(i32, i32)) -> u32 {
+        self.index(pos).map_or(UNREACHABLE, |i| self.cost[i])
+    }
+
+    pub fn reachable(&self, pos: &(i32, i32)) -> bool {
+        self.cost_at(pos) != UNREACHABLE
+    }
+
+    /// Cheapest successor of `pos` on the way to the goal
+    pub fn next_step(&self, tm: &TileMap, pos: &(i32, i32)) -> Option<(i32, i32)> {
+        if *pos == self.goal || !self.reachable(pos) {
+            return None;
+        }
+        tm.successors(pos).into_iter()
+            .filter(|(next, _)| self.reachable(next))
+            .min_by_key(|(next, cost)| self.cost_at(next) + cost)
+            .map(|(next, _)| next)
+    }
+
+    /// `pos` followed by at most `max_steps` tiles towards the goal
+    pub fn trace(&self, tm: &TileMap, pos: &(i32, i32), max_steps: usize) -> Vec<(i32, i32)> {
+        let mut steps = vec![*pos];
+        while steps.len() <= max_steps {
+            match self.next_step(tm, steps.last().unwrap()) {
+                Some(next) => steps.push(next),
+                None => break,
+            }
+        }
+        steps
+    }
+}
+
+/// Tiles with a step of `successors` into `pos`
+fn predecessors(tm: &TileMap, pos: &(i32, i32)) -> Vec<(i32, i32)> {
+    let mut out = Vec::new();
+    for &(dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)].iter() {
+        let from = (pos.0 + dx, pos.1 + dy);
+        if tm.is_passable(&from) {
+            out.push(from);
+        }
+    }
+    if tm.topology.diagonal {
+        for &(dx, dy) in [(-1, -1), (1, -1), (-1, 1), (1, 1)].iter() {
+            let from = (pos.0 + dx, pos.1 + dy);
+            if tm.is_passable(&from) && tm.can_step_diagonal(&from, -dx, -dy) {
+                out.push(from);
+            }
+        }
+    }
+    out
+}
+
+/// Flow fields of the destinations many units head to
+pub struct FlowFields {
+    fields: HashMap<(i32, i32), FlowField>,
+    /// Units heading to a destination before it gets a field, 0 never builds one
+    pub min_demand: u32,
+    /// Fields kept at once, the least recently used goes first
+    pub capacity: usize,
+}
+
+impl FlowFields {
+    pub fn new(min_demand: u32, capacity: usize) -> Self {
+        FlowFields { fields: HashMap::new(), min_demand, capacity }
+    }
+
+    pub fn get(&self, goal: &(i32, i32)) -> Option<&FlowField> {
+        self.fields.get(goal)
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    /// Keeps the field of `goal` fresh, building it when `demand` units
+    /// head there. Returns the expanded tiles when a field was built.
+    pub fn ensure(&mut self, tm: &TileMap, goal: (i32, i32), demand: u32, frame: u32) -> Option<usize> {
+        if let Some(field) = self.fields.get_mut(&goal) {
+            field.last_used = frame;
+            return None;
+        }
+        if self.min_demand == 0 || self.capacity == 0 || demand < self.min_demand {
+            return None;
+        }
+        if self.fields.len() >= self.capacity {
+            let oldest = self.fields.values().min_by_key(|f| f.last_used).map(|f| f.goal);
+            if let Some(oldest) = oldest {
+                self.fields.remove(&oldest);
+            }
+        }
+        let (mut field, expanded) = FlowField::build(tm, goal);
+        field.last_used = frame;
+        self.fields.insert(goal, field);
+        Some(expanded)
+    }
+
+    /// Builds every field again after the map changed, returns how many
+    pub fn rebuild(&mut self, tm: &TileMap) -> usize {
+        for field in self.fields.values_mut() {
+            let last_used = field.last_used;
+            *field = FlowField::build(tm, field.goal).0;
+            field.last_used = last_used;
+        }
+        self.fields.len()
+    }
+}
+
+pub struct FlowFieldResource(pub FlowFields);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecs::astar::{SearchOptions};
+    use crate::ecs::pathing;
+
+    fn trace_cost(tm: &TileMap, steps: &[(i32, i32)]) -> u32 {
+        steps.windows(2).map(|s| tm.step_cost(&s[0], &s[1])).sum()
+    }
+
+    #[test]
+    fn trace_costs_the_same_as_astar() {
+        let mut tm = TileMap::from_rows(&[
+            "..........",
+            ".RRRRRRRR.",
+            ".Rlla.lrR.",
+            ".R.aaaa.R.",
+            ".RRRR.llR.",
+            ".l..RRRRR.",
+            "..........",
+        ]);
+        for &diagonal in [false, true].iter() {
+            tm.topology.diagonal = diagonal;
+            let goal = (7, 2);
+            let (field, _) = FlowField::build(&tm, goal);
+            for start in [(1, 1), (3, 3), (1, 5), (6, 4), (8, 5)].iter() {
+                let steps = field.trace(&tm, start, 100);
+                assert_eq!(*steps.last().unwrap(), goal, "{:?} diagonal {}", start, diagonal);
+                let reference = pathing::astar_path(&tm, start, &goal, &SearchOptions::default());
+                assert_eq!(Some(trace_cost(&tm, &steps)), reference.path.map(|(_, cost)| cost), "{:?} diagonal {}", start, diagonal);
+            }
+        }
+    }
+
+    #[test]
+    fn trace_stops_at_max_steps() {
+        let tm = TileMap::from_rows(&[
+            "........",
+            ".RRRRRR.",
+            "........",
+        ]);
+        let (field, _) = FlowField::build(&tm, (6, 1));
+        assert_eq!(field.trace(&tm, &(1, 1), 3), vec![(1, 1), (2, 1), (3, 1), (4, 1)]);
+        assert!(!field.reachable(&(0, 0)));
+    }
+}
@@ -0,0 +1,351 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+use crate::ecs::submap::{CornerCutting, TileMap, STRAIGHT_STEP_COST};
+
+type Pos = (i32, i32);
+
+/// Road tiles all cost the same, which is what jump point pruning needs.
+/// The start and goal may sit next to the road.
+struct RoadGrid<'a> {
+    tm: &'a TileMap,
+    start: Pos,
+    goal: Pos,
+    diagonal: bool,
+}
+
+impl<'a> RoadGrid<'a> {
+    fn walkable(&self, x: i32, y: i32) -> bool {
+        if x < 0 || y < 0 || x as usize >= self.tm.w || y as usize >= self.tm.h {
+            return false;
+        }
+        (x, y) == self.start || (x, y) == self.goal || self.tm.is_road_tile(&(x, y))
+    }
+
+    /// Directions left to explore from `node` once reached from `parent`
+    fn neighbours(&self, node: Pos, parent: Option<Pos>) -> Vec<Pos> {
+        let (x, y) = node;
+        let mut out = Vec::new();
+        let parent = match parent {
+            Some(p) => p,
+            None => {
+                for &(dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)].iter() {
+                    out.push((x + dx, y + dy));
+                }
+                if self.diagonal {
+                    for &(dx, dy) in [(-1, -1), (1, -1), (-1, 1), (1, 1)].iter() {
+                        if self.walkable(x + dx, y) && self.walkable(x, y + dy) {
+                            out.push((x + dx, y + dy));
+                        }
+                    }
+                }
+                return out;
+            }
+        };
+        let dx = (x - parent.0).signum();
+        let dy = (y - parent.1).signum();
+
+        if !self.diagonal {
+            if dx != 0 {
+                out.extend_from_slice(&[(x, y - 1), (x, y + 1), (x + dx, y)]);
+            } else {
+                out.extend_from_slice(&[(x - 1, y), (x + 1, y), (x, y + dy)]);
+            }
+        } else if dx != 0 && dy != 0 {
+            let next_x = self.walkable(x + dx, y);
+            let next_y = self.walkable(x, y + dy);
+            if next_y {
+                out.push((x, y + dy));
+            }
+            if next_x {
+                out.push((x + dx, y));
+            }
+            if next_x && next_y {
+                out.push((x + dx, y + dy));
+            }
+            if self.walkable(x - dx, y) && next_y {
+                out.push((x - dx, y + dy));
+            }
+            if next_x && self.walkable(x, y - dy) {
+                out.push((x + dx, y - dy));
+            }
+        } else if dx != 0 {
+            let next = self.walkable(x + dx, y);
+            let down = self.walkable(x, y + 1);
+            let up = self.walkable(x, y - 1);
+            if next {
+                out.push((x + dx, y));
+                if down {
+                    out.push((x + dx, y + 1));
+                }
+                if up {
+                    out.push((x + dx, y - 1));
+                }
+            }
+            if down {
+                out.push((x, y + 1));
+            }
+            if up {
+                out.push((x, y - 1));
+            }
+        } else {
+            let next = self.walkable(x, y + dy);
+            let right = self.walkable(x + 1, y);
+            let left = self.walkable(x - 1, y);
+            if next {
+                out.push((x, y + dy));
+                if right {
+                    out.push((x + 1, y + dy));
+                }
+                if left {
+                    out.push((x - 1, y + dy));
+                }
+            }
+            if right {
+                out.push((x + 1, y));
+            }
+            if left {
+                out.push((x - 1, y));
+            }
+        }
+        out
+    }
+
+    /// Walks from `from` towards `dir` until the goal or a tile where the
+    /// path may have to turn
+    fn jump(&self, from: Pos, dir: Pos) -> Option<Pos> {
+        let (dx, dy) = dir;
+        let (mut x, mut y) = (from.0 + dx, from.1 + dy);
+        loop {
+            if !self.walkable(x, y) {
+                return None;
+            }
+            if (x, y) == self.goal {
+                return Some((x, y));
+            }
+            if dx != 0 && dy != 0 {
+                if self.jump((x, y), (dx, 0)).is_some() || self.jump((x, y), (0, dy)).is_some() {
+                    return Some((x, y));
+                }
+            } else if dx != 0 {
+                if (self.walkable(x, y - 1) && !self.walkable(x - dx, y - 1))
+                    || (self.walkable(x, y + 1) && !self.walkable(x - dx, y + 1)) {
+                    return Some((x, y));
+                }
+            } else {
+                if (self.walkable(x - 1, y) && !self.walkable(x - 1, y - dy))
+                    || (self.walkable(x + 1, y) && !self.walkable(x + 1, y - dy)) {
+                    return Some((x, y));
+                }
+                // on 4 directions vertical runs stop where a horizontal one finds something
+                if !self.diagonal && (self.jump((x, y), (1, 0)).is_some() || self.jump((x, y), (-1, 0)).is_some()) {
+                    return Some((x, y));
+                }
+            }
+            if self.diagonal && !(self.walkable(x + dx, y) && self.walkable(x, y + dy)) {
+                return None;
+            }
+            x += dx;
+            y += dy;
+        }
+    }
+
+    /// Cost of the straight or diagonal line between two jump points
+    fn distance(&self, a: Pos, b: Pos) -> u32 {
+        let dx = (a.0 - b.0).abs() as u32;
+        let dy = (a.1 - b.1).abs() as u32;
+        let (short, long) = (dx.min(dy), dx.max(dy));
+        STRAIGHT_STEP_COST * (long - short) + self.tm.topology.diagonal_cost * short
+    }
+}
+
+/// Every tile between consecutive jump points
+fn expand(jump_points: &[Pos]) -> Vec<Pos> {
+    let mut path = vec![jump_points[0]];
+    for pair in jump_points.windows(2) {
+        let (mut x, mut y) = pair[0];
+        let dx = (pair[1].0 - x).signum();
+        let dy = (pair[1].1 - y).signum();
+        while (x, y) != pair[1] {
+            x += dx;
+            y += dy;
+            path.push((x, y));
+        }
+    }
+    path
+}
+
+/// The tile is a road or next to one, where a road-only search can start or end
+pub fn near_road(tm: &TileMap, pos: &Pos) -> bool {
+    [(0, 0), (-1, 0), (1, 0), (0, -1), (0, 1)].iter().any(|&(dx, dy)| {
+        let (x, y) = (pos.0 + dx, pos.1 + dy);
+        x >= 0 && y >= 0 && (x as usize) < tm.w && (y as usize) < tm.h && tm.is_road_tile(&(x, y))
+    })
+}
+
+/// What entering a tile off the road costs at least on top of a road tile,
+/// those tiles cost 5 times as much
+const OFF_ROAD_EXTRA: u32 = 4 * STRAIGHT_STEP_COST;
+
+/// No path leaving the road can be cheaper than a road path of `cost` from `start`
+/// to `goal`: every step costs at least what the heuristic counts for it, and every
+/// tile a path enters off the road `OFF_ROAD_EXTRA` more, the goal being entered by
+/// all of them. Only holds on 4 directions, where A* and JPS step alike.
+pub fn road_path_is_cheapest(tm: &TileMap, start: &Pos, goal: &Pos, cost: u32) -> bool {
+    let goal_extra = if tm.is_road_tile(goal) { 0 } else { OFF_ROAD_EXTRA };
+    cost <= tm.heuristic(start, goal) + goal_extra + OFF_ROAD_EXTRA
+}
+
+/// Jump Point Search over road tiles. Faster than `astar` along the long
+/// uniform road corridors but blind to alleys and land, so it returns None
+/// when the only way leaves the road, and costs more than A* where an alley
+/// is a shortcut, see `road_path_is_cheapest`. Diagonal maps are only supported
+/// with `CornerCutting::Never`, and still cost more than A* which may cut across
+/// the corner of a land tile.
+/// `expanded` counts the jump points taken from the open list, the search
+/// gives up after `max_expanded` of them. The cost is in the unit of
+/// `TileMap::successors`.
+pub fn jps(tm: &TileMap, start: &Pos, goal: &Pos, max_expanded: Option<usize>, expanded: &mut usize) -> Option<(Vec<Pos>, u32)> {
+    if tm.topology.diagonal && tm.topology.corner_cutting != CornerCutting::Never {
+        return None;
+    }
+    let grid = RoadGrid { tm, start: *start, goal: *goal, diagonal: tm.topology.diagonal };
+    let mut best: HashMap<Pos, (u32, Option<Pos>)> = HashMap::new();
+    let mut open = BinaryHeap::new();
+    best.insert(*start, (0, None));
+    open.push(Reverse((tm.heuristic(start, goal), 0, *start)));
+
+    let mut searched = 0;
+    while let Some(Reverse((_, g, node))) = open.pop() {
+        let parent = match best.get(&node) {
+            Some(&(best_g, _)) if best_g < g => continue,
+            Some(&(_, parent)) => parent,
+            None => continue,
+        };
+        if max_expanded.map_or(false, |max| searched >= max) {
+            return None;
+        }
+        searched += 1;
+        *expanded += 1;
+        if node == *goal {
+            let mut jump_points = vec![node];
+            while let Some(&(_, Some(p))) = best.get(jump_points.last().unwrap()) {
+                jump_points.push(p);
+            }
+            jump_points.reverse();
+            let path = expand(&jump_points);
+            let cost = path.windows(2).map(|s| tm.step_cost(&s[0], &s[1])).sum();
+            return Some((path, cost));
+        }
+        for n in grid.neighbours(node, parent) {
+            if let Some(jp) = grid.jump(node, (n.0 - node.0, n.1 - node.1)) {
+                let next_g = g + grid.distance(node, jp);
+                if best.get(&jp).map_or(true, |&(b, _)| next_g < b) {
+                    best.insert(jp, (next_g, Some(node)));
+                    open.push(Reverse((next_g + tm.heuristic(&jp, goal), next_g, jp)));
+                }
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecs::astar::{SearchOptions};
+    use crate::ecs::pathing;
+
+    /// Blocks of land with an alley or two, every shortcut off the road costs more
+    fn blocks() -> TileMap {
+        TileMap::from_rows(&[
+            "...........",
+            ".RRRRRRRRR.",
+            ".RlllRaalR.",
+            ".RlllRlllR.",
+            ".RlllRlllR.",
+            ".RRRRRRRRR.",
+            ".RllaRlllR.",
+            ".RRRRRRRRR.",
+            "...........",
+        ])
+    }
+
+    const PAIRS: [(Pos, Pos); 5] = [
+        ((1, 1), (9, 7)),
+        ((2, 2), (8, 7)),
+        ((6, 2), (3, 6)),
+        ((4, 7), (9, 1)),
+        ((7, 4), (2, 6)),
+    ];
+
+    #[test]
+    fn same_cost_as_astar() {
+        let tm = blocks();
+        for (start, goal) in PAIRS.iter() {
+            let mut expanded = 0;
+            let (path, cost) = jps(&tm, start, goal, None, &mut expanded).unwrap();
+            let reference = pathing::astar_path(&tm, start, goal, &SearchOptions::default());
+            assert_eq!(Some(cost), reference.path.map(|(_, cost)| cost), "{:?} to {:?}", start, goal);
+            assert_eq!((path[0], *path.last().unwrap()), (*start, *goal));
+        }
+    }
+
+    /// Two roads joined at the left end and by one alley tile in the middle
+    fn alley_shortcut() -> TileMap {
+        TileMap::from_rows(&[
+            "............",
+            ".RRRRRRRRRR.",
+            ".Rllllallll.",
+            ".RRRRRRRRRR.",
+            "............",
+        ])
+    }
+
+    #[test]
+    fn find_path_costs_the_same_as_astar() {
+        let shortcut = ((6, 1), (6, 3));
+        let mut expanded = 0;
+        let (_, road_cost) = jps(&alley_shortcut(), &shortcut.0, &shortcut.1, None, &mut expanded).unwrap();
+        assert!(!road_path_is_cheapest(&alley_shortcut(), &shortcut.0, &shortcut.1, road_cost));
+
+        let maps = [(blocks(), PAIRS.to_vec()), (alley_shortcut(), vec![shortcut, ((1, 1), (10, 3)), ((10, 1), (10, 3)), ((3, 3), (8, 1))])];
+        for (tm, pairs) in maps.iter() {
+            for (start, goal) in pairs.iter() {
+                let search = pathing::find_path(tm, start, goal, pathing::PathAlgorithm::Jps, &SearchOptions::default());
+                let reference = pathing::astar_path(tm, start, goal, &SearchOptions::default());
+                assert_eq!(search.path.map(|(_, cost)| cost), reference.path.map(|(_, cost)| cost), "{:?} to {:?}", start, goal);
+            }
+        }
+    }
+
+    #[test]
+    fn find_path_leaves_diagonal_maps_to_astar() {
+        let mut tm = blocks();
+        tm.topology.diagonal = true;
+        for (start, goal) in PAIRS.iter() {
+            let search = pathing::find_path(&tm, start, goal, pathing::PathAlgorithm::Jps, &SearchOptions::default());
+            let reference = pathing::astar_path(&tm, start, goal, &SearchOptions::default());
+            assert_eq!(search.path, reference.path);
+        }
+    }
+
+    #[test]
+    fn gives_up_after_max_expanded() {
+        let tm = blocks();
+        let mut expanded = 0;
+        assert!(jps(&tm, &(1, 1), &(9, 7), Some(1), &mut expanded).is_none());
+        assert_eq!(expanded, 1);
+    }
+
+    #[test]
+    fn leaves_tiles_away_from_roads_to_astar() {
+        let tm = blocks();
+        assert!(near_road(&tm, &(2, 2)));
+        assert!(!near_road(&tm, &(3, 3)));
+        let search = pathing::find_path(&tm, &(1, 1), &(3, 3), pathing::PathAlgorithm::Jps, &SearchOptions::default());
+        let reference = pathing::astar_path(&tm, &(1, 1), &(3, 3), &SearchOptions::default());
+        assert_eq!(search.path, reference.path);
+        assert_eq!(search.expanded, reference.expanded);
+    }
+}
@@ -0,0 +1,326 @@
+use super::*;
+use crate::ecs::types::{*};
+use crate::ecs::components::{*};
+use crate::ecs::submap::{TileMap};
+use crate::ecs::sync::{ClientSyncResource};
+use crate::ecs::flow_field::{FlowFieldResource};
+use crate::ecs::connectivity::{ConnectivityResource};
+use crate::ecs::parcel::{LandParcelResource};
+use crate::ecs::building::{BuildingsResource};
+use legion::prelude::*;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TileKind {
+    Clear,
+    Land,
+    Road,
+    Resource,
+    Alley,
+}
+
+impl TileKind {
+    pub fn from_name(name: &str) -> Option<TileKind> {
+        match name {
+            "clear" => Some(TileKind::Clear),
+            "land" => Some(TileKind::Land),
+            "road" => Some(TileKind::Road),
+            "resource" => Some(TileKind::Resource),
+            "alley" => Some(TileKind::Alley),
+            _ => None,
+        }
+    }
+
+    pub fn tile_value(&self) -> u8 {
+        match self {
+            TileKind::Clear => 0,
+            TileKind::Land => 1,
+            TileKind::Road => 6,
+            TileKind::Resource => 8,
+            TileKind::Alley => 11,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TileEditReport {
+    pub old_tile: u8,
+    pub tile: u8,
+    pub invalidated_paths: usize,
+    pub repathed_units: usize,
+    pub rebuilt_flow_fields: usize,
+    /// Parcels split, merged or cleared away, with their ownership
+    pub removed_parcels: Vec<u32>,
+    /// Buildings torn down: the one standing on the tile and those no longer
+    /// on a single parcel of their owner
+    pub removed_buildings: Vec<u32>,
+}
+
+/// Drops every cached path going through one of `tiles`, returns how many
+fn invalidate_paths(pw: &mut PathwayHashMapResource, tiles: &[(i32, i32)]) -> usize {
+    let before = pw.0.len();
+    pw.0.retain(|_, paths| !paths.iter().any(|p| tiles.contains(p)));
+    before - pw.0.len()
+}
+
+/// Drops the move segment announced to a unit walking near the edited tile, and
+/// stops its step when it leads into the tile or past a corner it now cuts
+fn restart_move(tm: &TileMap, pos: &LandPos, mv: &mut Moving, tile: (i32, i32)) {
+    // the announced segment is stale, the next step starts a new one
+    mv.seg_steps = 0;
+    let into_tile = !tm.can_move_to(&tile) && (pos.0 + mv.vx, pos.1 + mv.vy) == tile;
+    let past_corner = mv.vx != 0 && mv.vy != 0 && !tm.can_step_diagonal(&(pos.0, pos.1), mv.vx, mv.vy);
+    if into_tile || past_corner {
+        mv.vx = 0;
+        mv.vy = 0;
+        mv.step = 0;
+    }
+}
+
+/// Changes one tile of the map.
+/// Paths through the tile or its neighbours are dropped from the cache, since the move
+/// rules of a tile depend on its neighbours, and units walking them lose their cursor
+/// and plan again from where they stand, units following a rebuilt flow field take
+/// their next step from it. Resource nodes follow resource tiles. A tile-changed event is
+/// emitted and sent to the sync clients watching the chunk. Flow fields, land parcels
+/// and the connected regions around the tile are rebuilt, a building on the tile or left
+/// off its owner's parcel is torn down.
+pub fn set_tile(world: &mut World, resources: &mut Resources, x: i32, y: i32, kind: TileKind) -> Result<TileEditReport, String> {
+    let tile = kind.tile_value();
+    let old_tile = match resources.get_mut::<TileMapResource>() {
+        Some(mut tm) => {
+            let tm = &mut tm.0;
+            if x < 1 || y < 1 || x >= tm.w as i32 - 1 || y >= tm.h as i32 - 1 {
+                return Err(format!("{},{} is outside the map", x, y));
+            }
+            let old_tile = tm.data[y as usize][x as usize];
+            tm.data[y as usize][x as usize] = tile;
+            old_tile
+        },
+        None => return Err("no tile map".to_string()),
+    };
+    let mut report = TileEditReport { old_tile, tile, invalidated_paths: 0, repathed_units: 0, rebuilt_flow_fields: 0, removed_parcels: Vec::new(), removed_buildings: Vec::new() };
+    if old_tile == tile {
+        return Ok(report);
+    }
+
+    if let (Some(tm), Some(mut conn)) = (resources.get::<TileMapResource>(), resources.get_mut::<ConnectivityResource>()) {
+        conn.0.update(&tm.0, &(x, y));
+    }
+    if let (Some(tm), Some(mut flow)) = (resources.get::<TileMapResource>(), resources.get_mut::<FlowFieldResource>()) {
+        report.rebuilt_flow_fields = flow.0.rebuild(&tm.0);
+    }
+    if let (Some(tm), Some(mut parcels)) = (resources.get::<TileMapResource>(), resources.get_mut::<LandParcelResource>()) {
+        report.removed_parcels = parcels.0.rebuild(&tm.0);
+    }
+    let removed = match (resources.get::<LandParcelResource>(), resources.get_mut::<BuildingsResource>()) {
+        (Some(parcels), Some(mut buildings)) => {
+            let mut removed = Vec::new();
+            if let Some(id) = buildings.0.at(&(x, y)).map(|b| b.id) {
+                buildings.0.remove(id);
+                removed.push(id);
+            }
+            removed.extend(buildings.0.reparcel(&parcels.0).into_iter().map(|b| b.id));
+            removed
+        },
+        _ => Vec::new(),
+    };
+    if removed.len() > 0 {
+        let mut entities = Vec::new();
+        let query = <(Read<BuildingId>)>::query();
+        for (entity, id) in query.iter_entities_mut(world) {
+            if removed.contains(&id.0) {
+                entities.push(entity.index());
+            }
+        }
+        for index in entities {
+            let _ = admin::despawn(world, resources, index);
+        }
+    }
+    report.removed_buildings = removed;
+
+    let affected = [(x, y), (x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)];
+    report.invalidated_paths = match resources.get_mut::<PathwayHashMapResource>() {
+        Some(mut pw) => invalidate_paths(&mut pw, &affected),
+        None => 0,
+    };
+
+    let mut stale = Vec::new();
+    let mut flow_followers = 0;
+    {
+        let tm = resources.get::<TileMapResource>().ok_or_else(|| "no tile map".to_string())?;
+        let query = <(Read<PathCursor>, Read<LandPos>, Write<Moving>)>::query()
+            .filter(!component::<NewPos>());
+        for (entity, (cursor, pos, mut mv)) in query.iter_entities_mut(world) {
+            if !cursor.remaining().iter().any(|p| affected.contains(p)) {
+                continue;
+            }
+            restart_move(&tm.0, &pos, &mut mv, (x, y));
+            stale.push(entity);
+        }
+
+        // units following a flow field keep no path, the rebuilt field gives their next step
+        if let Some(flow) = resources.get::<FlowFieldResource>() {
+            let query = <(Read<FollowPath>, Read<LandPos>, Write<Moving>)>::query()
+                .filter(!component::<NewPos>() & !component::<PathCursor>());
+            for (fp, pos, mut mv) in query.iter_mut(world) {
+                if flow.0.get(&(fp.tx, fp.ty)).is_none() {
+                    continue;
+                }
+                restart_move(&tm.0, &pos, &mut mv, (x, y));
+                flow_followers += 1;
+            }
+        }
+    }
+    report.repathed_units = stale.len() + flow_followers;
+    for entity in stale {
+        let _ = world.remove_component::<PathCursor>(entity);
+    }
+
+    let resource_model = BuildingModelType::ResourceNode as u32;
+    if old_tile == TileKind::Resource.tile_value() {
+        let mut nodes = Vec::new();
+        let query = <(Read<LandPos>)>::query()
+            .filter(tag_value(&Model(resource_model)));
+        for (entity, pos) in query.iter_entities_mut(world) {
+            if (pos.0, pos.1) == (x, y) {
+                nodes.push(entity.index());
+            }
+        }
+        for index in nodes {
+            let _ = admin::despawn(world, resources, index);
+        }
+    } else if kind == TileKind::Resource {
+        world.insert(
+            (Model(resource_model), Static),
+            vec![(LandPos(x, y),)]
+        );
+    }
+
+    let chunk_key = LandPos(x, y).get_hash_map_key();
+    let frame = resources.get::<GameConfigResource>().map_or(0, |conf| conf.number_of_updates);
+    if let Some(mut p) = resources.get_mut::<EmitEventResource>() {
+        p.0.push((chunk_key, LunaciaWorldEvent::EventTileChanged{
+            frame: frame,
+            tx: x,
+            ty: y,
+            tile: tile,
+        }));
+    }
+    if let Some(mut sync) = resources.get_mut::<ClientSyncResource>() {
+        sync.broadcast_tile(frame, chunk_key, x, y, tile);
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecs::flow_field::{FlowFields};
+    use crate::ecs::connectivity::{Connectivity};
+    use crate::ecs::sync::{SyncMessage};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    fn edit_world(rows: &[&str]) -> (World, Resources) {
+        let world = Universe::new().create_world();
+        let mut resources = Resources::default();
+        resources.insert(TileMapResource(TileMap::from_rows(rows)));
+        resources.insert(PathwayHashMapResource(HashMap::new()));
+        resources.insert(FlowFieldResource(FlowFields::new(1, 4)));
+        (world, resources)
+    }
+
+    #[test]
+    fn flow_field_followers_stop_before_a_cleared_tile() {
+        let (mut world, mut resources) = edit_world(&[
+            ".........",
+            ".RRRRRRR.",
+            ".RRRRRRR.",
+            ".........",
+        ]);
+        {
+            let tm = resources.get::<TileMapResource>().unwrap();
+            let mut flow = resources.get_mut::<FlowFieldResource>().unwrap();
+            flow.0.ensure(&tm.0, (7, 1), 1, 0);
+        }
+        let walking = Moving { vx: 1, vy: 0, step: 3, maxstep: 10, seg_steps: 5, ..Moving::new() };
+        let units = world.insert((), vec![
+            (LandPos(1, 1), FollowPath::new(1, 1, 7, 1), walking),
+            (LandPos(1, 2), FollowPath::new(1, 2, 7, 1), walking),
+            (LandPos(3, 2), FollowPath::new(3, 2, 1, 2), walking),
+        ]).to_vec();
+
+        let report = set_tile(&mut world, &mut resources, 2, 1, TileKind::Clear).unwrap();
+        assert_eq!(report.rebuilt_flow_fields, 1);
+        assert_eq!(report.repathed_units, 2);
+
+        // heading into the cleared tile, it stops where it stands
+        let mv = *world.get_component::<Moving>(units[0]).unwrap();
+        assert_eq!((mv.vx, mv.vy, mv.step, mv.seg_steps), (0, 0, 0, 0));
+        // the step goes on, the segment is announced again
+        let mv = *world.get_component::<Moving>(units[1]).unwrap();
+        assert_eq!((mv.vx, mv.vy, mv.step, mv.seg_steps), (1, 0, 3, 0));
+        // no field for its goal, nothing changes
+        assert_eq!(*world.get_component::<Moving>(units[2]).unwrap(), walking);
+    }
+
+    #[test]
+    fn set_tile_drops_paths_splits_regions_and_tells_the_chunk() {
+        let (mut world, mut resources) = edit_world(&[
+            ".........",
+            ".RRRRRRR.",
+            ".......R.",
+            ".RRRRRRR.",
+            ".........",
+        ]);
+        let conn = Connectivity::build(&resources.get::<TileMapResource>().unwrap().0);
+        resources.insert(ConnectivityResource(conn));
+        {
+            let mut pw = resources.get_mut::<PathwayHashMapResource>().unwrap();
+            pw.0.insert(((3, 1), (5, 1)), Arc::new(vec![(3, 1), (4, 1), (5, 1)]));
+            pw.0.insert(((1, 1), (3, 1)), Arc::new(vec![(1, 1), (2, 1), (3, 1)]));
+            pw.0.insert(((5, 3), (7, 3)), Arc::new(vec![(5, 3), (6, 3), (7, 3)]));
+        }
+        let chunk_key = LandPos(4, 1).get_hash_map_key();
+        let mut sync = ClientSyncResource::default();
+        sync.subscribe(7, &vec![chunk_key]);
+        sync.subscribe(8, &vec![chunk_key + 1000]);
+        resources.insert(sync);
+
+        let report = set_tile(&mut world, &mut resources, 4, 1, TileKind::Clear).unwrap();
+        assert_eq!((report.old_tile, report.tile), (6, 0));
+
+        // the path through the tile and the one ending next to it are gone
+        assert_eq!(report.invalidated_paths, 2);
+        let pw = resources.get::<PathwayHashMapResource>().unwrap();
+        assert!(pw.0.contains_key(&((5, 3), (7, 3))));
+
+        let conn = resources.get::<ConnectivityResource>().unwrap();
+        assert!(!conn.0.reachable(&(1, 1), &(5, 1)));
+        assert!(conn.0.reachable(&(5, 1), &(1, 3)));
+
+        let mut sync = resources.get_mut::<ClientSyncResource>().unwrap();
+        let outbox = sync.drain_outbox();
+        assert_eq!(outbox.len(), 1);
+        match &outbox[0] {
+            (7, SyncMessage::TileChanged{chunk_key: key, x: 4, y: 1, tile: 0, ..}) => assert_eq!(*key, chunk_key),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn set_tile_keeps_everything_when_the_tile_is_unchanged() {
+        let (mut world, mut resources) = edit_world(&[
+            ".....",
+            ".RRR.",
+            ".....",
+        ]);
+        resources.get_mut::<PathwayHashMapResource>().unwrap().0
+            .insert(((1, 1), (3, 1)), Arc::new(vec![(1, 1), (2, 1), (3, 1)]));
+        let report = set_tile(&mut world, &mut resources, 2, 1, TileKind::Road).unwrap();
+        assert_eq!(report.invalidated_paths, 0);
+        assert_eq!(resources.get::<PathwayHashMapResource>().unwrap().0.len(), 1);
+        assert!(set_tile(&mut world, &mut resources, 0, 1, TileKind::Road).is_err());
+    }
+}
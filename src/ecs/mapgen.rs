@@ -0,0 +1,154 @@
+use super::*;
+use crate::ecs::submap::{TileMap};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+const ROAD_TILE: u8 = 6;
+const ALLEY_TILE: u8 = 11;
+const RESOURCE_TILE: u8 = 8;
+
+#[derive(Clone, Debug)]
+pub struct MapGenParams {
+    pub width: usize,
+    pub height: usize,
+    pub seed: u64,
+    /// Distance between two roads, blocks of `block_size - 1` tiles sit in between
+    pub block_size: usize,
+    /// Chance for a block to be split by an alley
+    pub alley_density: f64,
+    /// Chance for a reachable plot tile to be a resource tile
+    pub resource_density: f64,
+}
+
+impl MapGenParams {
+    pub fn new(width: usize, height: usize, seed: u64) -> Self {
+        MapGenParams {
+            width,
+            height,
+            seed,
+            block_size: 6,
+            alley_density: 0.7,
+            resource_density: 0.05,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.width < 3 || self.height < 3 {
+            return Err("map must be at least 3x3".to_string());
+        }
+        if self.block_size < 3 {
+            return Err("block_size must be at least 3".to_string());
+        }
+        if !(0.0..=1.0).contains(&self.alley_density) || !(0.0..=1.0).contains(&self.resource_density) {
+            return Err("densities must be within 0..=1".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Lays out a road grid with the border left empty. Each block between roads
+/// gets one land type from 1 to 5 and maybe an alley through its middle.
+/// Plot tiles that `can_move_to` rejects are left empty, so every land and
+/// resource tile of the result can be reached.
+pub fn generate(params: &MapGenParams) -> Result<TileMap, String> {
+    params.validate()?;
+    let mut rng = StdRng::seed_from_u64(params.seed);
+    let (w, h) = (params.width, params.height);
+    let b = params.block_size;
+    let mut tm = TileMap::new(w, h);
+
+    // roads on x, y = 1, 1 + b, 1 + 2b ...
+    let is_road = |v: usize, max: usize| v >= 1 && v < max - 1 && (v - 1) % b == 0;
+
+    let blocks_x = (w + b - 1) / b;
+    let blocks_y = (h + b - 1) / b;
+    let mut block_land = vec![vec![1u8; blocks_x]; blocks_y];
+    // 0: no alley, 1: vertical, 2: horizontal
+    let mut block_alley = vec![vec![0u8; blocks_x]; blocks_y];
+    for by in 0..blocks_y {
+        for bx in 0..blocks_x {
+            block_land[by][bx] = rng.gen_range(1, 6);
+            if rng.gen_bool(params.alley_density) {
+                block_alley[by][bx] = if rng.gen_bool(0.5) { 1 } else { 2 };
+            }
+        }
+    }
+
+    for y in 1..h - 1 {
+        for x in 1..w - 1 {
+            let tile = if is_road(x, w) || is_road(y, h) {
+                ROAD_TILE
+            } else {
+                let (bx, by) = ((x - 1) / b, (y - 1) / b);
+                let (ox, oy) = ((x - 1) % b, (y - 1) % b);
+                match block_alley[by][bx] {
+                    1 if ox == b / 2 => ALLEY_TILE,
+                    2 if oy == b / 2 => ALLEY_TILE,
+                    _ => block_land[by][bx],
+                }
+            };
+            tm.data[y][x] = tile;
+        }
+    }
+
+    // plots need a road or alley next to them
+    for y in 1..h - 1 {
+        for x in 1..w - 1 {
+            let tile = tm.data[y][x];
+            if tile == ROAD_TILE || tile == ALLEY_TILE {
+                continue;
+            }
+            let pos = (x as i32, y as i32);
+            if !tm.can_move_to(&pos) {
+                tm.data[y][x] = 0;
+            } else if rng.gen_bool(params.resource_density) {
+                tm.data[y][x] = RESOURCE_TILE;
+            }
+        }
+    }
+    Ok(tm)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecs::connectivity::{Connectivity};
+
+    #[test]
+    fn same_seed_same_map() {
+        let params = MapGenParams::new(40, 30, 7);
+        let tm = generate(&params).unwrap();
+        assert_eq!((tm.w, tm.h), (40, 30));
+        assert_eq!((tm.data.len(), tm.data[0].len()), (30, 40));
+        assert_eq!(tm, generate(&params).unwrap());
+        assert!(tm != generate(&MapGenParams::new(40, 30, 8)).unwrap());
+    }
+
+    #[test]
+    fn every_tile_is_reachable_from_the_roads() {
+        for seed in 0..5 {
+            let tm = generate(&MapGenParams::new(45, 38, seed)).unwrap();
+            let conn = Connectivity::build(&tm);
+            let roads = conn.region(&(1, 1));
+            assert!(roads.is_some());
+            for y in 0..tm.h {
+                for x in 0..tm.w {
+                    let tile = tm.data[y][x];
+                    let border = x == 0 || y == 0 || x == tm.w - 1 || y == tm.h - 1;
+                    assert!(!border || tile == 0, "{},{} seed {}", x, y, seed);
+                    if tile != 0 {
+                        assert_eq!(conn.region(&(x as i32, y as i32)), roads, "{},{} seed {}", x, y, seed);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_bad_params() {
+        assert!(generate(&MapGenParams::new(2, 10, 0)).is_err());
+        let mut params = MapGenParams::new(20, 20, 0);
+        params.alley_density = 1.5;
+        assert!(generate(&params).is_err());
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Upper bounds in seconds, tuned around the 200 ms update budget
+const DURATION_BUCKETS: [f64; 12] = [0.0001, 0.0005, 0.001, 0.002, 0.005, 0.01, 0.025, 0.05, 0.1, 0.2, 0.5, 1.0];
+const EXPANSION_BUCKETS: [f64; 8] = [10.0, 100.0, 500.0, 1000.0, 5000.0, 10000.0, 50000.0, 100000.0];
+
+/// `{labels}`, nothing for an empty label set
+fn braces(labels: &str) -> String {
+    if labels.is_empty() { String::new() } else { format!("{{{}}}", labels) }
+}
+
+#[derive(Clone, Debug)]
+pub struct Histogram {
+    bounds: Vec<f64>,
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    pub fn new(bounds: &[f64]) -> Self {
+        Histogram { bounds: bounds.to_vec(), counts: vec![0; bounds.len()], sum: 0.0, count: 0 }
+    }
+
+    pub fn observe(&mut self, value: f64) {
+        for (i, bound) in self.bounds.iter().enumerate() {
+            if value <= *bound {
+                self.counts[i] += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    pub fn mean(&self) -> f64 {
+        if self.count == 0 { 0.0 } else { self.sum / self.count as f64 }
+    }
+
+    fn write_prometheus(&self, out: &mut String, name: &str, labels: &str) {
+        let sep = if labels.is_empty() { "" } else { "," };
+        for (bound, count) in self.bounds.iter().zip(self.counts.iter()) {
+            let _ = writeln!(out, "{}_bucket{{{}{}le=\"{}\"}} {}", name, labels, sep, bound, count);
+        }
+        let _ = writeln!(out, "{}_bucket{{{}{}le=\"+Inf\"}} {}", name, labels, sep, self.count);
+        let _ = writeln!(out, "{}_sum{} {}", name, braces(labels), self.sum);
+        let _ = writeln!(out, "{}_count{} {}", name, braces(labels), self.count);
+    }
+}
+
+/// Simulation counters shared by every system, safe to update from parallel systems
+pub struct SimMetrics {
+    systems: Mutex<HashMap<&'static str, Histogram>>,
+    ticks: Mutex<Histogram>,
+    astar_expansions: Mutex<Histogram>,
+    entity_counts: Mutex<HashMap<u32, usize>>,
+    pub astar_searches: AtomicU64,
+    pub astar_failures: AtomicU64,
+    /// Failed searches stopped by a limit of `SearchOptions`
+    pub astar_cut_offs: AtomicU64,
+    /// Searches not run, the target being out of the region of the unit
+    pub astar_skipped: AtomicU64,
+    pub events_emitted: AtomicU64,
+    pub flow_fields_built: AtomicU64,
+    pub deliveries_stored: AtomicU64,
+    /// Deliveries without a storage with room on the parcel
+    pub deliveries_refused: AtomicU64,
+}
+
+impl SimMetrics {
+    pub fn new() -> Self {
+        SimMetrics {
+            systems: Mutex::new(HashMap::new()),
+            ticks: Mutex::new(Histogram::new(&DURATION_BUCKETS)),
+            astar_expansions: Mutex::new(Histogram::new(&EXPANSION_BUCKETS)),
+            entity_counts: Mutex::new(HashMap::new()),
+            astar_searches: AtomicU64::new(0),
+            astar_failures: AtomicU64::new(0),
+            astar_cut_offs: AtomicU64::new(0),
+            astar_skipped: AtomicU64::new(0),
+            events_emitted: AtomicU64::new(0),
+            flow_fields_built: AtomicU64::new(0),
+            deliveries_stored: AtomicU64::new(0),
+            deliveries_refused: AtomicU64::new(0),
+        }
+    }
+
+    /// Records the time until the returned guard is dropped under `name`
+    pub fn time_system(&self, name: &'static str) -> SystemTimer {
+        SystemTimer { metrics: self, name, start: Instant::now() }
+    }
+
+    pub fn observe_system(&self, name: &'static str, elapsed: Duration) {
+        let mut systems = self.systems.lock().unwrap();
+        systems.entry(name)
+            .or_insert_with(|| Histogram::new(&DURATION_BUCKETS))
+            .observe(elapsed.as_secs_f64());
+    }
+
+    pub fn observe_tick(&self, elapsed: Duration) {
+        self.ticks.lock().unwrap().observe(elapsed.as_secs_f64());
+    }
+
+    pub fn observe_astar(&self, expanded: usize, found: bool) {
+        self.astar_searches.fetch_add(1, Ordering::Relaxed);
+        if !found {
+            self.astar_failures.fetch_add(1, Ordering::Relaxed);
+        }
+        self.astar_expansions.lock().unwrap().observe(expanded as f64);
+    }
+
+    pub fn observe_search_cut_off(&self) {
+        self.astar_cut_offs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn observe_search_skipped(&self) {
+        self.astar_skipped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn observe_flow_field(&self) {
+        self.flow_fields_built.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn observe_delivery(&self, stored: bool) {
+        if stored {
+            self.deliveries_stored.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.deliveries_refused.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn add_events(&self, count: usize) {
+        self.events_emitted.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    pub fn set_entity_counts(&self, counts: HashMap<u32, usize>) {
+        *self.entity_counts.lock().unwrap() = counts;
+    }
+
+    /// Prometheus text exposition format
+    pub fn render_prometheus(&self) -> String {
+        let mut out = String::new();
+
+        let _ = writeln!(out, "# TYPE lunacia_tick_duration_seconds histogram");
+        self.ticks.lock().unwrap().write_prometheus(&mut out, "lunacia_tick_duration_seconds", "");
+
+        let _ = writeln!(out, "# TYPE lunacia_system_duration_seconds histogram");
+        let systems = self.systems.lock().unwrap();
+        let mut names: Vec<&&'static str> = systems.keys().collect();
+        names.sort();
+        for name in names {
+            let labels = format!("system=\"{}\"", name);
+            systems[*name].write_prometheus(&mut out, "lunacia_system_duration_seconds", &labels);
+        }
+
+        let _ = writeln!(out, "# TYPE lunacia_entities gauge");
+        let counts = self.entity_counts.lock().unwrap();
+        let mut models: Vec<&u32> = counts.keys().collect();
+        models.sort();
+        for model in models {
+            let _ = writeln!(out, "lunacia_entities{{model=\"{}\"}} {}", model, counts[model]);
+        }
+
+        let _ = writeln!(out, "# TYPE lunacia_astar_expanded_nodes histogram");
+        self.astar_expansions.lock().unwrap().write_prometheus(&mut out, "lunacia_astar_expanded_nodes", "");
+        let counters = [
+            ("lunacia_astar_searches_total", &self.astar_searches),
+            ("lunacia_astar_failures_total", &self.astar_failures),
+            ("lunacia_astar_cut_offs_total", &self.astar_cut_offs),
+            ("lunacia_astar_skipped_total", &self.astar_skipped),
+            ("lunacia_events_emitted_total", &self.events_emitted),
+            ("lunacia_flow_fields_built_total", &self.flow_fields_built),
+            ("lunacia_deliveries_stored_total", &self.deliveries_stored),
+            ("lunacia_deliveries_refused_total", &self.deliveries_refused),
+        ];
+        for (name, counter) in counters.iter() {
+            let _ = writeln!(out, "# TYPE {} counter", name);
+            let _ = writeln!(out, "{} {}", name, counter.load(Ordering::Relaxed));
+        }
+        out
+    }
+
+    /// Short human readable summary for the console
+    pub fn render_summary(&self) -> String {
+        let mut out = String::new();
+        let ticks = self.ticks.lock().unwrap();
+        let _ = writeln!(out, "ticks: {} mean {:.3} ms", ticks.count, ticks.mean() * 1000.0);
+        let systems = self.systems.lock().unwrap();
+        let mut rows: Vec<(&&'static str, &Histogram)> = systems.iter().collect();
+        rows.sort_by(|a, b| b.1.sum.partial_cmp(&a.1.sum).unwrap_or(std::cmp::Ordering::Equal));
+        for (name, h) in rows {
+            let _ = writeln!(out, "  {:<32} mean {:.3} ms total {:.1} ms", name, h.mean() * 1000.0, h.sum * 1000.0);
+        }
+        let expansions = self.astar_expansions.lock().unwrap();
+        let _ = writeln!(out, "astar: {} searches, {} failed ({} cut off), {} skipped as unreachable, mean {:.0} expanded",
+            self.astar_searches.load(Ordering::Relaxed),
+            self.astar_failures.load(Ordering::Relaxed),
+            self.astar_cut_offs.load(Ordering::Relaxed),
+            self.astar_skipped.load(Ordering::Relaxed),
+            expansions.mean());
+        let _ = writeln!(out, "flow fields: {} built", self.flow_fields_built.load(Ordering::Relaxed));
+        let _ = writeln!(out, "deliveries: {} stored, {} refused",
+            self.deliveries_stored.load(Ordering::Relaxed), self.deliveries_refused.load(Ordering::Relaxed));
+        let _ = writeln!(out, "events: {}", self.events_emitted.load(Ordering::Relaxed));
+        let _ = write!(out, "entities: {:?}", *self.entity_counts.lock().unwrap());
+        out
+    }
+}
+
+pub struct SystemTimer<'a> {
+    metrics: &'a SimMetrics,
+    name: &'static str,
+    start: Instant,
+}
+
+impl<'a> Drop for SystemTimer<'a> {
+    fn drop(&mut self) {
+        self.metrics.observe_system(self.name, self.start.elapsed());
+    }
+}
+
+#[derive(Clone)]
+pub struct MetricsResource(pub Arc<SimMetrics>);
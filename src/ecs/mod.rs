@@ -1,5 +1,17 @@
+pub mod admin;
 pub mod astar;
+pub mod building;
 pub mod components;
+pub mod connectivity;
+pub mod flow_field;
+pub mod jps;
+pub mod mapgen;
+pub mod map_edit;
+pub mod metrics;
+pub mod parcel;
+pub mod pathing;
+pub mod render;
 pub mod submap;
+pub mod sync;
 pub mod systems;
 pub mod types;
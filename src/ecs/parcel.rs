@@ -0,0 +1,256 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::ecs::submap::{TileMap};
+
+const NO_PARCEL: u32 = u32::MAX;
+
+/// Contiguous land tiles of the same type
+#[derive(Clone, Debug, Serialize)]
+pub struct LandParcel {
+    pub id: u32,
+    /// Tile value of the parcel, 1 to 5
+    pub land_type: u8,
+    /// First tile in row order, the parcel keeps its id as long as it keeps this tile
+    pub origin: (i32, i32),
+    /// Bounding rectangle
+    pub x: i32,
+    pub y: i32,
+    pub w: i32,
+    pub h: i32,
+    pub tiles: usize,
+    /// Parcel tiles next to a road or an alley
+    pub entrances: Vec<(i32, i32)>,
+    pub owner: Option<u32>,
+}
+
+/// Every parcel of the map and the parcel of each tile
+#[derive(Clone, Debug)]
+pub struct LandParcels {
+    w: usize,
+    h: usize,
+    by_tile: Vec<u32>,
+    parcels: BTreeMap<u32, LandParcel>,
+    next_id: u32,
+}
+
+impl LandParcels {
+    pub fn build(tm: &TileMap) -> Self {
+        let mut parcels = LandParcels { w: tm.w, h: tm.h, by_tile: vec![NO_PARCEL; tm.w * tm.h], parcels: BTreeMap::new(), next_id: 0 };
+        for y in 0..tm.h as i32 {
+            for x in 0..tm.w as i32 {
+                if parcels.by_tile[parcels.index(&(x, y)).unwrap()] == NO_PARCEL && tm.is_land_tile(&(x, y)) {
+                    let id = parcels.next_id;
+                    parcels.next_id += 1;
+                    let parcel = parcels.flood(tm, (x, y), id);
+                    parcels.parcels.insert(id, parcel);
+                }
+            }
+        }
+        parcels
+    }
+
+    fn index(&self, pos: &(i32, i32)) -> Option<usize> {
+        if pos.0 < 0 || pos.1 < 0 || pos.0 as usize >= self.w || pos.1 as usize >= self.h {
+            return None;
+        }
+        Some(pos.1 as usize * self.w + pos.0 as usize)
+    }
+
+    /// Labels the tiles of the parcel of `start` with `id`
+    fn flood(&mut self, tm: &TileMap, start: (i32, i32), id: u32) -> LandParcel {
+        let land_type = tm.data[start.1 as usize][start.0 as usize];
+        let mut parcel = LandParcel { id, land_type, origin: start, x: start.0, y: start.1, w: 1, h: 1, tiles: 0, entrances: Vec::new(), owner: None };
+        let (mut x1, mut y1) = start;
+        let i = self.index(&start).unwrap();
+        self.by_tile[i] = id;
+        let mut open = VecDeque::new();
+        open.push_back(start);
+        while let Some(pos) = open.pop_front() {
+            parcel.tiles += 1;
+            parcel.x = parcel.x.min(pos.0);
+            parcel.y = parcel.y.min(pos.1);
+            x1 = x1.max(pos.0);
+            y1 = y1.max(pos.1);
+            let mut entrance = false;
+            for &(dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)].iter() {
+                let next = (pos.0 + dx, pos.1 + dy);
+                let j = match self.index(&next) {
+                    Some(j) => j,
+                    None => continue,
+                };
+                let tile = tm.data[next.1 as usize][next.0 as usize];
+                if tm.is_road_tile(&next) || tm.is_alley_tile(&next) {
+                    entrance = true;
+                } else if tile == land_type && self.by_tile[j] == NO_PARCEL {
+                    self.by_tile[j] = id;
+                    open.push_back(next);
+                }
+            }
+            if entrance {
+                parcel.entrances.push(pos);
+            }
+        }
+        parcel.w = x1 - parcel.x + 1;
+        parcel.h = y1 - parcel.y + 1;
+        parcel.entrances.sort();
+        parcel
+    }
+
+    /// Groups the tiles again after the map changed. A parcel keeps its id
+    /// and owner when it keeps its origin, new and split off parcels get
+    /// new ids. Returns the ids of the parcels that are gone.
+    pub fn rebuild(&mut self, tm: &TileMap) -> Vec<u32> {
+        let old = std::mem::replace(self, LandParcels {
+            w: tm.w,
+            h: tm.h,
+            by_tile: vec![NO_PARCEL; tm.w * tm.h],
+            parcels: BTreeMap::new(),
+            next_id: self.next_id,
+        });
+        for y in 0..tm.h as i32 {
+            for x in 0..tm.w as i32 {
+                let i = self.index(&(x, y)).unwrap();
+                if self.by_tile[i] != NO_PARCEL || !tm.is_land_tile(&(x, y)) {
+                    continue;
+                }
+                // scanning in the same order, a parcel is found again from its origin
+                let tile = tm.data[y as usize][x as usize];
+                let kept = old.at(&(x, y)).filter(|p| p.origin == (x, y) && p.land_type == tile);
+                let id = match kept {
+                    Some(p) => p.id,
+                    None => {
+                        let id = self.next_id;
+                        self.next_id += 1;
+                        id
+                    }
+                };
+                let mut parcel = self.flood(tm, (x, y), id);
+                parcel.owner = kept.and_then(|p| p.owner);
+                self.parcels.insert(id, parcel);
+            }
+        }
+        old.parcels.keys().filter(|id| !self.parcels.contains_key(id)).cloned().collect()
+    }
+
+    pub fn get(&self, id: u32) -> Option<&LandParcel> {
+        self.parcels.get(&id)
+    }
+
+    /// Parcel of a land tile
+    pub fn at(&self, pos: &(i32, i32)) -> Option<&LandParcel> {
+        let id = self.by_tile[self.index(pos)?];
+        self.parcels.get(&id)
+    }
+
+    pub fn owned_by(&self, owner: u32) -> Vec<&LandParcel> {
+        self.parcels.values().filter(|p| p.owner == Some(owner)).collect()
+    }
+
+    /// Gives a parcel to `owner`, or back to nobody
+    pub fn set_owner(&mut self, id: u32, owner: Option<u32>) -> Result<(), String> {
+        let parcel = self.parcels.get_mut(&id).ok_or_else(|| format!("parcel {}", id))?;
+        parcel.owner = owner;
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.parcels.len()
+    }
+}
+
+pub struct LandParcelResource(pub LandParcels);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn groups_adjacent_tiles_of_the_same_land() {
+        let mut tm = TileMap::from_rows(&[
+            ".......",
+            ".RRRRR.",
+            ".Rlll..",
+            ".Rl.ll.",
+            ".......",
+        ]);
+        tm.data[3][4] = 2;
+        tm.data[3][5] = 2;
+        let parcels = LandParcels::build(&tm);
+        assert_eq!(parcels.len(), 2);
+
+        let first = parcels.at(&(2, 2)).unwrap();
+        assert_eq!(parcels.at(&(2, 3)).unwrap().id, first.id);
+        assert_eq!((first.land_type, first.origin, first.tiles), (1, (2, 2), 4));
+        assert_eq!((first.x, first.y, first.w, first.h), (2, 2, 3, 2));
+        assert_eq!(first.entrances, vec![(2, 2), (2, 3), (3, 2), (4, 2)]);
+
+        // next to the first one but of another type
+        let second = parcels.at(&(4, 3)).unwrap();
+        assert!(second.id != first.id);
+        assert_eq!((second.land_type, second.tiles), (2, 2));
+        assert!(second.entrances.is_empty());
+
+        assert!(parcels.at(&(1, 1)).is_none());
+        assert!(parcels.at(&(3, 3)).is_none());
+        assert!(parcels.at(&(-1, 3)).is_none());
+    }
+
+    #[test]
+    fn owned_by_lists_the_parcels_of_an_owner() {
+        let tm = TileMap::from_rows(&[
+            ".......",
+            ".RRRRR.",
+            ".Rl.l..",
+            ".......",
+        ]);
+        let mut parcels = LandParcels::build(&tm);
+        let left = parcels.at(&(2, 2)).unwrap().id;
+        let right = parcels.at(&(4, 2)).unwrap().id;
+        parcels.set_owner(left, Some(7)).unwrap();
+        parcels.set_owner(right, Some(7)).unwrap();
+        parcels.set_owner(right, None).unwrap();
+        let owned: Vec<u32> = parcels.owned_by(7).iter().map(|p| p.id).collect();
+        assert_eq!(owned, vec![left]);
+        assert!(parcels.owned_by(8).is_empty());
+        assert!(parcels.set_owner(99, Some(7)).is_err());
+    }
+
+    #[test]
+    fn rebuild_keeps_ids_and_owners_through_splits_and_merges() {
+        let mut tm = TileMap::from_rows(&[
+            "........",
+            ".RRRRRR.",
+            ".Rlllll.",
+            "........",
+        ]);
+        let mut parcels = LandParcels::build(&tm);
+        let id = parcels.at(&(2, 2)).unwrap().id;
+        parcels.set_owner(id, Some(3)).unwrap();
+
+        // a road through the middle, the part with the origin keeps the parcel
+        tm.data[2][4] = 6;
+        assert!(parcels.rebuild(&tm).is_empty());
+        assert_eq!(parcels.len(), 2);
+        let kept = parcels.at(&(3, 2)).unwrap();
+        assert_eq!((kept.id, kept.owner, kept.tiles), (id, Some(3), 2));
+        let split = parcels.at(&(5, 2)).unwrap();
+        assert!(split.id != id);
+        assert_eq!((split.owner, split.tiles), (None, 2));
+        let split = split.id;
+
+        // land again, the split off parcel is gone
+        tm.data[2][4] = 1;
+        assert_eq!(parcels.rebuild(&tm), vec![split]);
+        let merged = parcels.at(&(6, 2)).unwrap();
+        assert_eq!((merged.id, merged.owner, merged.tiles), (id, Some(3), 5));
+
+        // cleared away
+        for x in 2..7 {
+            tm.data[2][x] = 0;
+        }
+        assert_eq!(parcels.rebuild(&tm), vec![id]);
+        assert_eq!(parcels.len(), 0);
+        assert!(parcels.owned_by(3).is_empty());
+    }
+}
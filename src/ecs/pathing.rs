@@ -0,0 +1,244 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use crate::ecs::astar::{self, SearchOptions, SearchOutcome};
+use crate::ecs::jps;
+use crate::ecs::submap::{TileMap};
+
+/// A planned path, shared by the path cache and every unit walking it
+pub type SharedPath = Arc<Vec<(i32, i32)>>;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PathAlgorithm {
+    AStar,
+    /// Jump Point Search along the roads on 4 directions. A* when that finds
+    /// nothing, the start or goal is away from the road, or a path leaving the
+    /// road may be cheaper
+    Jps,
+}
+
+impl PathAlgorithm {
+    pub fn from_name(name: &str) -> Option<PathAlgorithm> {
+        match name {
+            "astar" => Some(PathAlgorithm::AStar),
+            "jps" => Some(PathAlgorithm::Jps),
+            _ => None,
+        }
+    }
+}
+
+/// What a unit does once its retries are spent
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PathFallback {
+    /// Stops where it stands
+    None,
+    /// Walks to the reachable tile closest to its target
+    Nearest,
+}
+
+impl PathFallback {
+    pub fn from_name(name: &str) -> Option<PathFallback> {
+        match name {
+            "none" => Some(PathFallback::None),
+            "nearest" => Some(PathFallback::Nearest),
+            _ => None,
+        }
+    }
+}
+
+/// How a unit reacts when no path reaches its target
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PathFailurePolicy {
+    pub retries: u32,
+    /// Wait before the first retry, doubled for each of the next ones
+    pub retry_ms: u64,
+    pub fallback: PathFallback,
+}
+
+impl PathFailurePolicy {
+    /// Frames to wait before retry number `attempt`, counted from 1
+    pub fn retry_delay_frames(&self, attempt: u32, fixed_time_ms: u64) -> u32 {
+        let delay_ms = self.retry_ms << (attempt.max(1) - 1).min(16);
+        (delay_ms / fixed_time_ms.max(1)).max(1) as u32
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PathSearch {
+    /// Tiles from start to goal and their cost
+    pub path: Option<(Vec<(i32, i32)>, u32)>,
+    /// Nodes expanded by every search that ran
+    pub expanded: usize,
+    /// How the last search ended
+    pub outcome: SearchOutcome,
+}
+
+impl PathSearch {
+    /// The search stopped at a limit before proving the goal unreachable
+    pub fn cut_off(&self) -> bool {
+        self.outcome == SearchOutcome::ExpansionLimit || self.outcome == SearchOutcome::CostLimit
+    }
+}
+
+pub fn astar_path(tm: &TileMap, start: &(i32, i32), goal: &(i32, i32), options: &SearchOptions<u32>) -> PathSearch {
+    let search = astar::astar_with(start,
+        |&p| tm.successors(&p),
+        |p| tm.heuristic(p, goal),
+        |p| p == goal,
+        options);
+    PathSearch { path: search.path, expanded: search.stats.expanded, outcome: search.outcome }
+}
+
+/// Shortest path from `start` to `goal` with `algorithm`, A* stays within `options`
+pub fn find_path(tm: &TileMap, start: &(i32, i32), goal: &(i32, i32), algorithm: PathAlgorithm, options: &SearchOptions<u32>) -> PathSearch {
+    match algorithm {
+        // off the road JPS would flood the whole road network before giving up,
+        // and its diagonal steps keep to stricter corners than the ones of A*
+        PathAlgorithm::Jps if !tm.topology.diagonal && jps::near_road(tm, start) && jps::near_road(tm, goal) => {
+            let mut expanded = 0;
+            let road = jps::jps(tm, start, goal, options.max_expanded, &mut expanded)
+                .filter(|(_, cost)| options.max_cost.map_or(true, |max| *cost <= max));
+            let (path, cost) = match road {
+                Some(road) => road,
+                None => {
+                    let mut search = astar_path(tm, start, goal, options);
+                    search.expanded += expanded;
+                    return search;
+                }
+            };
+            if jps::road_path_is_cheapest(tm, start, goal, cost) {
+                return PathSearch { path: Some((path, cost)), expanded, outcome: SearchOutcome::Found };
+            }
+            // an alley may cut the road path short, A* only looks for paths as cheap
+            let bounded = SearchOptions { max_cost: Some(cost), ..*options };
+            let mut search = astar_path(tm, start, goal, &bounded);
+            search.expanded += expanded;
+            if search.path.is_none() {
+                search.path = Some((path, cost));
+                search.outcome = SearchOutcome::Found;
+            }
+            search
+        },
+        _ => astar_path(tm, start, goal, options),
+    }
+}
+
+/// Path to the reachable tile closest to `goal` by `TileMap::heuristic`, the cheapest
+/// one on ties, searching no further than `options`. None when no reachable tile
+/// is closer than `start`.
+pub fn nearest_reachable(tm: &TileMap, start: &(i32, i32), goal: &(i32, i32), options: &SearchOptions<u32>) -> Option<(Vec<(i32, i32)>, u32)> {
+    let search = astar::astar_with(start,
+        // units never stand on an empty tile
+        |&p| tm.successors(&p).into_iter().filter(move |(next, _)| tm.is_passable(next)),
+        |p| tm.heuristic(p, goal),
+        |p| p == goal,
+        options);
+    search.path.or(search.partial)
+}
+
+/// Above this many targets `nearest_target` runs a plain Dijkstra, the distance
+/// to every target costing more per tile than it saves
+const MAX_HEURISTIC_TARGETS: usize = 16;
+
+/// Path to the target of `targets` cheapest to reach from `start`, in a single
+/// search within `options`. The target is the last tile of the path.
+pub fn nearest_target(tm: &TileMap, start: &(i32, i32), targets: &[(i32, i32)], options: &SearchOptions<u32>) -> PathSearch {
+    if targets.is_empty() {
+        return PathSearch { path: None, expanded: 0, outcome: SearchOutcome::Unreachable };
+    }
+    let goals: HashSet<(i32, i32)> = targets.iter().cloned().collect();
+    let use_heuristic = targets.len() <= MAX_HEURISTIC_TARGETS;
+    let search = astar::astar_with(start,
+        |&p| tm.successors(&p),
+        |p| if use_heuristic { targets.iter().map(|t| tm.heuristic(p, t)).min().unwrap_or(0) } else { 0 },
+        |p| goals.contains(p),
+        options);
+    PathSearch { path: search.path, expanded: search.stats.expanded, outcome: search.outcome }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nearest_target_is_the_cheapest_not_the_closest() {
+        // (3, 3) is 3 tiles away across the land, (8, 1) is 7 tiles away along the road
+        let tm = TileMap::from_rows(&[
+            "..........",
+            ".RRRRRRRr.",
+            ".Rlll.....",
+            ".Rllr.....",
+            "..........",
+        ]);
+        let start = (1, 1);
+        let targets = [(3, 3), (8, 1)];
+        assert!(tm.heuristic(&start, &targets[0]) < tm.heuristic(&start, &targets[1]));
+
+        let search = nearest_target(&tm, &start, &targets, &SearchOptions::default());
+        let (path, cost) = search.path.unwrap();
+        assert_eq!(*path.last().unwrap(), (8, 1));
+        for target in targets.iter() {
+            let reference = astar_path(&tm, &start, target, &SearchOptions::default());
+            assert!(cost <= reference.path.unwrap().1, "{:?}", target);
+        }
+    }
+
+    #[test]
+    fn nearest_target_without_heuristic() {
+        let tm = TileMap::from_rows(&[
+            "..............",
+            ".RRRRRRRr.....",
+            ".Rlll.........",
+            ".Rllr.........",
+            "..............",
+            "..............",
+        ]);
+        // more targets than MAX_HEURISTIC_TARGETS, the empty ones out of reach
+        let mut targets: Vec<(i32, i32)> = (6..14).flat_map(|x| vec![(x, 4), (x, 5)]).collect();
+        targets.push((3, 3));
+        targets.push((8, 1));
+        assert!(targets.len() > MAX_HEURISTIC_TARGETS);
+        let search = nearest_target(&tm, &(1, 1), &targets, &SearchOptions::default());
+        assert_eq!(*search.path.unwrap().0.last().unwrap(), (8, 1));
+    }
+
+    #[test]
+    fn retry_delay_doubles_for_each_retry() {
+        let policy = PathFailurePolicy { retries: 3, retry_ms: 1000, fallback: PathFallback::None };
+        let delays: Vec<u32> = (1..4).map(|attempt| policy.retry_delay_frames(attempt, 200)).collect();
+        assert_eq!(delays, vec![5, 10, 20]);
+        // at least one frame, even with no wait
+        let policy = PathFailurePolicy { retry_ms: 0, ..policy };
+        assert_eq!(policy.retry_delay_frames(1, 200), 1);
+    }
+
+    #[test]
+    fn nearest_reachable_ends_on_a_passable_tile() {
+        let tm = TileMap::from_rows(&[
+            "..........",
+            ".RRRR..RR.",
+            "..........",
+        ]);
+        let (path, cost) = nearest_reachable(&tm, &(1, 1), &(8, 1), &SearchOptions::default()).unwrap();
+        assert_eq!(path, vec![(1, 1), (2, 1), (3, 1), (4, 1)]);
+        assert_eq!(Some(cost), astar_path(&tm, &(1, 1), &(4, 1), &SearchOptions::default()).path.map(|(_, cost)| cost));
+
+        // the target itself when it can be reached
+        let (path, _) = nearest_reachable(&tm, &(1, 1), &(3, 1), &SearchOptions::default()).unwrap();
+        assert_eq!(*path.last().unwrap(), (3, 1));
+        // nothing closer than where it stands
+        assert!(nearest_reachable(&tm, &(8, 1), &(8, 2), &SearchOptions::default()).is_none());
+    }
+
+    #[test]
+    fn nearest_reachable_stops_at_max_expanded() {
+        let tm = TileMap::from_rows(&[
+            "..........",
+            ".RRRR..RR.",
+            "..........",
+        ]);
+        let options = SearchOptions { max_expanded: Some(2), ..SearchOptions::default() };
+        let (path, _) = nearest_reachable(&tm, &(1, 1), &(8, 1), &options).unwrap();
+        assert_eq!(path, vec![(1, 1), (2, 1)]);
+    }
+}
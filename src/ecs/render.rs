@@ -0,0 +1,247 @@
+use super::*;
+use crate::ecs::types::{*};
+use crate::ecs::components::{*};
+use crate::ecs::submap::{TileMap};
+use crate::ecs::building::{BuildingsResource};
+use legion::prelude::*;
+use std::fs::{self, File};
+use std::path::{Component, Path, PathBuf};
+use std::io::Write as IoWrite;
+
+/// Side of a chunk in tiles, see `LandPos::get_hash_map_key`
+const CHUNK_SIZE: i32 = 6;
+
+/// Largest region drawn as ASCII, a terminal does not show more
+pub const MAX_ASCII_SIZE: i32 = 200;
+
+#[derive(Clone, Copy, Debug)]
+pub struct RenderOptions {
+    pub x: i32,
+    pub y: i32,
+    pub w: i32,
+    pub h: i32,
+    pub entities: bool,
+    pub paths: bool,
+    pub chunks: bool,
+}
+
+impl RenderOptions {
+    pub fn new(x: i32, y: i32, w: i32, h: i32) -> Self {
+        RenderOptions { x, y, w, h, entities: true, paths: true, chunks: true }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Cell {
+    Empty,
+    Land,
+    Road,
+    Alley,
+    Resource,
+    Other,
+    Path,
+    Axie,
+    Chimera,
+    ResourceNode,
+    Building,
+    Entity,
+}
+
+impl Cell {
+    fn from_tile(tm: &TileMap, pos: &(i32, i32)) -> Cell {
+        if tm.is_land_tile(pos) {
+            Cell::Land
+        } else if tm.is_road_tile(pos) {
+            Cell::Road
+        } else if tm.is_alley_tile(pos) {
+            Cell::Alley
+        } else if tm.is_resource_tile(pos) {
+            Cell::Resource
+        } else if tm.data[pos.1 as usize][pos.0 as usize] == 0 {
+            Cell::Empty
+        } else {
+            Cell::Other
+        }
+    }
+
+    fn from_model(model: u32) -> Cell {
+        if model == UnitModelType::Axie as u32 {
+            Cell::Axie
+        } else if model == UnitModelType::Chimera as u32 {
+            Cell::Chimera
+        } else if model == BuildingModelType::ResourceNode as u32 {
+            Cell::ResourceNode
+        } else if model == BuildingModelType::Storage as u32 || model == BuildingModelType::House as u32 {
+            Cell::Building
+        } else {
+            Cell::Entity
+        }
+    }
+
+    fn ascii(&self) -> char {
+        match self {
+            Cell::Empty => ' ',
+            Cell::Land => '.',
+            Cell::Road => '#',
+            Cell::Alley => '+',
+            Cell::Resource => '*',
+            Cell::Other => '?',
+            Cell::Path => 'o',
+            Cell::Axie => 'A',
+            Cell::Chimera => 'C',
+            Cell::ResourceNode => 'R',
+            Cell::Building => 'B',
+            Cell::Entity => 'E',
+        }
+    }
+
+    fn rgb(&self) -> [u8; 3] {
+        match self {
+            Cell::Empty => [0, 0, 0],
+            Cell::Land => [70, 140, 60],
+            Cell::Road => [150, 150, 150],
+            Cell::Alley => [200, 200, 200],
+            Cell::Resource => [200, 170, 40],
+            Cell::Other => [120, 60, 140],
+            Cell::Path => [40, 200, 220],
+            Cell::Axie => [40, 80, 230],
+            Cell::Chimera => [220, 40, 40],
+            Cell::ResourceNode => [240, 120, 0],
+            Cell::Building => [140, 90, 50],
+            Cell::Entity => [255, 255, 255],
+        }
+    }
+}
+
+/// Region of the map as cells, row by row, entities drawn over paths over tiles
+fn build_cells(world: &mut World, resources: &Resources, opts: &RenderOptions) -> Result<Vec<Vec<Cell>>, String> {
+    let tm = resources.get::<TileMapResource>().ok_or_else(|| "no tile map".to_string())?;
+    let tm = &tm.0;
+    if opts.w <= 0 || opts.h <= 0 {
+        return Err("empty region".to_string());
+    }
+    let x0 = opts.x.max(0);
+    let y0 = opts.y.max(0);
+    let x1 = (opts.x + opts.w).min(tm.w as i32);
+    let y1 = (opts.y + opts.h).min(tm.h as i32);
+    if x0 >= x1 || y0 >= y1 {
+        return Err(format!("{},{} {}x{} is outside the map", opts.x, opts.y, opts.w, opts.h));
+    }
+
+    let mut cells = Vec::new();
+    for y in y0..y1 {
+        cells.push((x0..x1).map(|x| Cell::from_tile(tm, &(x, y))).collect::<Vec<Cell>>());
+    }
+    let mut put = |x: i32, y: i32, cell: Cell| {
+        if x >= x0 && x < x1 && y >= y0 && y < y1 {
+            cells[(y - y0) as usize][(x - x0) as usize] = cell;
+        }
+    };
+
+    if opts.paths {
+        let query = <(Read<PathCursor>)>::query();
+        for cursor in query.iter_mut(world) {
+            for p in cursor.remaining().iter() {
+                put(p.0, p.1, Cell::Path);
+            }
+        }
+    }
+
+    if opts.entities {
+        // the whole footprint, the entity only stands on its top left tile
+        if let Some(buildings) = resources.get::<BuildingsResource>() {
+            for building in buildings.0.iter() {
+                for (x, y) in building.tiles() {
+                    put(x, y, Cell::Building);
+                }
+            }
+        }
+        let query = <(Read<LandPos>, Tagged<Model>)>::query();
+        for (pos, model) in query.iter_mut(world) {
+            put(pos.0, pos.1, Cell::from_model(model.0));
+        }
+    }
+    Ok(cells)
+}
+
+/// Draws a region as text, chunk boundaries become `|` and `-` lines
+pub fn render_ascii(world: &mut World, resources: &Resources, opts: &RenderOptions) -> Result<String, String> {
+    if opts.w > MAX_ASCII_SIZE || opts.h > MAX_ASCII_SIZE {
+        return Err(format!("ASCII renders are limited to {}x{}", MAX_ASCII_SIZE, MAX_ASCII_SIZE));
+    }
+    let cells = build_cells(world, resources, opts)?;
+    let x0 = opts.x.max(0);
+    let y0 = opts.y.max(0);
+
+    let mut out = String::new();
+    for (row, line) in cells.iter().enumerate() {
+        let y = y0 + row as i32;
+        if opts.chunks && y % CHUNK_SIZE == 0 && row > 0 {
+            for col in 0..line.len() {
+                let x = x0 + col as i32;
+                if x % CHUNK_SIZE == 0 && col > 0 {
+                    out.push('+');
+                }
+                out.push('-');
+            }
+            out.push('\n');
+        }
+        for (col, cell) in line.iter().enumerate() {
+            let x = x0 + col as i32;
+            if opts.chunks && x % CHUNK_SIZE == 0 && col > 0 {
+                out.push('|');
+            }
+            out.push(cell.ascii());
+        }
+        out.push('\n');
+    }
+    out.push_str("legend: . land  # road  + alley  * resource tile  o path  A axie  C chimera  R resource node  B building  E other\n");
+    Ok(out)
+}
+
+/// Draws a region as a binary PPM image, `scale` pixels per tile
+pub fn render_ppm(world: &mut World, resources: &Resources, opts: &RenderOptions, scale: usize) -> Result<Vec<u8>, String> {
+    let cells = build_cells(world, resources, opts)?;
+    let x0 = opts.x.max(0);
+    let y0 = opts.y.max(0);
+    let scale = scale.max(1);
+    let h = cells.len() * scale;
+    let w = cells[0].len() * scale;
+
+    let mut out = format!("P6\n{} {}\n255\n", w, h).into_bytes();
+    for py in 0..h {
+        let row = py / scale;
+        let y = y0 + row as i32;
+        for px in 0..w {
+            let col = px / scale;
+            let x = x0 + col as i32;
+            let mut rgb = cells[row][col].rgb();
+            let on_chunk_edge = (x % CHUNK_SIZE == 0 && px % scale == 0) || (y % CHUNK_SIZE == 0 && py % scale == 0);
+            if opts.chunks && scale > 1 && on_chunk_edge {
+                rgb = [rgb[0] / 2, rgb[1] / 2, rgb[2] / 2];
+            }
+            out.extend_from_slice(&rgb);
+        }
+    }
+    Ok(out)
+}
+
+/// Path of an image named by a client, a plain file name inside `dir`
+pub fn output_path(dir: &str, name: &str) -> Result<PathBuf, String> {
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(file)), None) => Ok(Path::new(dir).join(file)),
+        _ => Err(format!("{} is not a plain file name", name)),
+    }
+}
+
+/// Writes the image to `name` inside the `render_dir` of the game config, returns its path
+pub fn write_ppm(world: &mut World, resources: &Resources, opts: &RenderOptions, scale: usize, name: &str) -> Result<String, String> {
+    let dir = resources.get::<GameConfigResource>().map(|conf| conf.render_dir.clone()).ok_or_else(|| "no game config".to_string())?;
+    let path = output_path(&dir, name)?;
+    let image = render_ppm(world, resources, opts, scale)?;
+    fs::create_dir_all(&dir).map_err(|e| format!("can not create {}: {}", dir, e))?;
+    let mut file = File::create(&path).map_err(|e| format!("can not create {}: {}", path.display(), e))?;
+    file.write_all(&image).map_err(|e| format!("can not write {}: {}", path.display(), e))?;
+    Ok(path.display().to_string())
+}
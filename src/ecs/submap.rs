@@ -1,21 +1,81 @@
 
 use std::fs::File;
-use std::io::Read;
+use std::io::{Read, Write};
 // pub type Board = Vec<Vec<u8>>;
 // use types::TileMap;
 
 
+/// Cost of a straight step, diagonal costs are given in the same unit
+pub const STRAIGHT_STEP_COST: u32 = 10;
+/// `Moving::maxstep` of a straight step
+pub const STRAIGHT_STEP_LENGTH: u64 = 1000;
+
+/// When a diagonal step may pass next to impassable tiles
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum CornerCutting {
+    /// both tiles beside the step must be passable
+    Never,
+    /// one passable tile beside the step is enough
+    OneSide,
+    Always,
+}
+
+impl CornerCutting {
+    pub fn from_name(name: &str) -> Option<CornerCutting> {
+        match name {
+            "never" => Some(CornerCutting::Never),
+            "one_side" => Some(CornerCutting::OneSide),
+            "always" => Some(CornerCutting::Always),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct MovementTopology {
+    /// 8 directions instead of 4
+    pub diagonal: bool,
+    /// Cost of a diagonal step for a straight one of `STRAIGHT_STEP_COST`
+    pub diagonal_cost: u32,
+    pub corner_cutting: CornerCutting,
+}
+
+impl Default for MovementTopology {
+    fn default() -> Self {
+        MovementTopology { diagonal: false, diagonal_cost: 14, corner_cutting: CornerCutting::Never }
+    }
+}
+
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct TileMap {
     pub w : usize,
     pub h : usize,
-    pub data: Vec<Vec<u8>>
+    pub data: Vec<Vec<u8>>,
+    pub topology: MovementTopology,
 }
 
 impl TileMap {
     pub fn new(w: usize, h: usize) -> Self {
-        let mut data = vec![vec![0; h]; w];
-        TileMap {w, h, data}
+        let mut data = vec![vec![0; w]; h];
+        TileMap {w, h, data, topology: MovementTopology::default()}
+    }
+
+    /// Map drawn row by row: `R` road, `a` alley, `l` land, `r` resource, anything else empty
+    #[cfg(test)]
+    pub fn from_rows(rows: &[&str]) -> Self {
+        let mut tm = TileMap::new(rows[0].len(), rows.len());
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                tm.data[y][x] = match c {
+                    'R' => 6,
+                    'a' => 11,
+                    'l' => 1,
+                    'r' => 8,
+                    _ => 0,
+                };
+            }
+        }
+        tm
     }
 
     fn get_tile_value(&self, pos: &(i32, i32)) -> u8 {
@@ -23,13 +83,21 @@ impl TileMap {
     }
 
     pub fn load_map(&mut self) -> std::io::Result<()>  {
-        let mut file_sub_map = File::open("sub-map.bin")?;
+        self.load_map_from("sub-map.bin")
+    }
+
+    pub fn load_map_from(&mut self, path: &str) -> std::io::Result<()>  {
+        let mut file_sub_map = File::open(path)?;
         // read the same file back into a Vec of bytes
         let mut sub_map_buffer = Vec::<u8>::new();
         file_sub_map.read_to_end(&mut sub_map_buffer)?;
 
         let w = self.w;
         let h = self.h;
+        if sub_map_buffer.len() < w * h {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData,
+                format!("{} has {} tiles, expected {}x{}", path, sub_map_buffer.len(), w, h)));
+        }
 
         for y in 0..h {
             for x in 0..w {
@@ -40,7 +108,16 @@ impl TileMap {
         Ok(())
     }
 
-    
+    /// Writes the tiles row by row, the format `load_map_from` reads
+    pub fn save_map_to(&self, path: &str) -> std::io::Result<()>  {
+        let mut buffer = Vec::<u8>::with_capacity(self.w * self.h);
+        for y in 0..self.h {
+            buffer.extend_from_slice(&self.data[y][..self.w]);
+        }
+        let mut file = File::create(path)?;
+        file.write_all(&buffer)
+    }
+
     pub fn is_land_tile(&self, pos: &(i32, i32)) -> bool {
         let tile = self.data[pos.1 as usize][pos.0 as usize];
         match tile {
@@ -120,13 +197,89 @@ impl TileMap {
     }
     
 
+    pub fn is_passable(&self, pos: &(i32, i32)) -> bool {
+        pos.0 >= 0 && pos.1 >= 0 && (pos.0 as usize) < self.w && (pos.1 as usize) < self.h
+            && self.get_tile_value(pos) != 0
+    }
+
+    /// A diagonal step from `pos` by (dx, dy) is allowed by the corner rule
+    pub fn can_step_diagonal(&self, pos: &(i32, i32), dx: i32, dy: i32) -> bool {
+        let target = (pos.0 + dx, pos.1 + dy);
+        if !self.is_passable(&target) {
+            return false;
+        }
+        let side_x = self.is_passable(&(pos.0 + dx, pos.1));
+        let side_y = self.is_passable(&(pos.0, pos.1 + dy));
+        match self.topology.corner_cutting {
+            CornerCutting::Never => side_x && side_y,
+            CornerCutting::OneSide => side_x || side_y,
+            CornerCutting::Always => true,
+        }
+    }
+
     pub fn successors(&self, pos: &(i32, i32)) -> Vec<((i32, i32), u32)> {
         let tile = self.get_tile_value(pos);
         // println!("successors  {:?} {:?}: {:?}", pos.0, pos.1, tile);
         match tile{
             0 => {Vec::new()},
-            _ => {vec![(pos.0 - 1, pos.1), (pos.0 + 1, pos.1), (pos.0, pos.1 - 1), (pos.0, pos.1 + 1)]
-                .into_iter().map(|p| (p, self.get_move_cost(&p))).collect()}
+            _ => {
+                let mut next: Vec<((i32, i32), u32)> = vec![(pos.0 - 1, pos.1), (pos.0 + 1, pos.1), (pos.0, pos.1 - 1), (pos.0, pos.1 + 1)]
+                    .into_iter().map(|p| (p, self.step_cost(pos, &p))).collect();
+                if self.topology.diagonal {
+                    for &(dx, dy) in [(-1, -1), (1, -1), (-1, 1), (1, 1)].iter() {
+                        if self.can_step_diagonal(pos, dx, dy) {
+                            let p = (pos.0 + dx, pos.1 + dy);
+                            next.push((p, self.step_cost(pos, &p)));
+                        }
+                    }
+                }
+                next
+            }
+        }
+    }
+
+    /// Search cost of a step between two neighbour tiles
+    pub fn step_cost(&self, from: &(i32, i32), to: &(i32, i32)) -> u32 {
+        if from.0 != to.0 && from.1 != to.1 {
+            self.get_move_cost(to) * self.topology.diagonal_cost
+        } else {
+            self.get_move_cost(to) * STRAIGHT_STEP_COST
+        }
+    }
+
+    /// Lower bound of the path cost between two tiles, Manhattan distance
+    /// on 4 directions and octile distance on 8
+    pub fn heuristic(&self, from: &(i32, i32), to: &(i32, i32)) -> u32 {
+        let dx = (from.0 - to.0).abs() as u32;
+        let dy = (from.1 - to.1).abs() as u32;
+        if self.topology.diagonal {
+            let (short, long) = (dx.min(dy), dx.max(dy));
+            // a diagonal never costs more than two straight steps in the search
+            let diagonal_cost = self.topology.diagonal_cost.min(2 * STRAIGHT_STEP_COST);
+            STRAIGHT_STEP_COST * (long - short) + diagonal_cost * short
+        } else {
+            STRAIGHT_STEP_COST * (dx + dy)
+        }
+    }
+
+    /// Tiles at most one step apart: Manhattan distance on 4 directions,
+    /// Chebyshev distance on 8
+    pub fn within_one_step(&self, a: &(i32, i32), b: &(i32, i32)) -> bool {
+        let dx = (a.0 - b.0).abs();
+        let dy = (a.1 - b.1).abs();
+        if self.topology.diagonal {
+            dx.max(dy) <= 1
+        } else {
+            dx + dy <= 1
+        }
+    }
+
+    /// `Moving::maxstep` of a step by (vx, vy), longer for diagonals
+    pub fn step_length(&self, vx: i32, vy: i32) -> u64 {
+        if vx != 0 && vy != 0 {
+            STRAIGHT_STEP_LENGTH * self.topology.diagonal_cost as u64 / STRAIGHT_STEP_COST as u64
+        } else {
+            STRAIGHT_STEP_LENGTH
         }
     }
 }
@@ -151,3 +304,19 @@ impl TileMap {
 //     }
 //     Ok((tilemap))
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn within_one_step_counts_diagonals_on_8_directions() {
+        let mut tm = TileMap::new(5, 5);
+        assert!(tm.within_one_step(&(2, 2), &(2, 3)));
+        assert!(!tm.within_one_step(&(2, 2), &(3, 3)));
+        tm.topology.diagonal = true;
+        assert!(tm.within_one_step(&(2, 2), &(3, 3)));
+        assert!(tm.within_one_step(&(2, 2), &(2, 2)));
+        assert!(!tm.within_one_step(&(2, 2), &(4, 3)));
+    }
+}
@@ -0,0 +1,241 @@
+use super::*;
+use crate::ecs::types::{*};
+use crate::ecs::components::{*};
+use legion::prelude::*;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Sent states kept per chunk while waiting for an ack,
+/// a client falling further behind gets a new snapshot
+const MAX_PENDING_STATES: usize = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct SyncEntityState {
+    pub model: u32,
+    pub x: i32,
+    pub y: i32,
+}
+
+/// entity index -> state of every entity inside one chunk
+pub type ChunkState = HashMap<u32, SyncEntityState>;
+
+/// Sent to clients as JSON, `{"type": "snapshot", ...}`
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SyncMessage {
+    Snapshot {
+        seq: u32,
+        frame: u32,
+        chunk_key: i32,
+        entities: Vec<(u32, SyncEntityState)>,
+    },
+    /// Changes against the state the client acked with `base_seq`
+    Delta {
+        seq: u32,
+        base_seq: u32,
+        frame: u32,
+        chunk_key: i32,
+        upserts: Vec<(u32, SyncEntityState)>,
+        removes: Vec<u32>,
+    },
+    /// Not part of the acked chunk state, sent once when a tile is edited
+    TileChanged {
+        frame: u32,
+        chunk_key: i32,
+        x: i32,
+        y: i32,
+        tile: u8,
+    },
+    /// Sent once when a unit of the chunk gives up reaching (tx, ty),
+    /// `fallback` is the tile it walks to instead
+    PathFailed {
+        frame: u32,
+        chunk_key: i32,
+        id: u32,
+        sx: i32,
+        sy: i32,
+        tx: i32,
+        ty: i32,
+        fallback: Option<(i32, i32)>,
+    },
+}
+
+#[derive(Clone, Default)]
+pub struct ClientChunkSync {
+    acked: Option<(u32, ChunkState)>,
+    pending: VecDeque<(u32, ChunkState)>,
+}
+
+impl ClientChunkSync {
+    fn last_sent(&self) -> Option<&ChunkState> {
+        match self.pending.back() {
+            Some((_, state)) => Some(state),
+            None => self.acked.as_ref().map(|(_, state)| state),
+        }
+    }
+
+    fn ack(&mut self, seq: u32) {
+        while let Some((pending_seq, _)) = self.pending.front() {
+            if *pending_seq > seq {
+                break;
+            }
+            self.acked = self.pending.pop_front();
+        }
+    }
+
+    fn reset(&mut self) {
+        self.acked = None;
+        self.pending.clear();
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct ClientSync {
+    pub chunks: HashMap<i32, ClientChunkSync>,
+    next_seq: u32,
+}
+
+#[derive(Clone, Default)]
+pub struct ClientSyncResource {
+    pub clients: HashMap<u32, ClientSync>,
+    pub outbox: Vec<(u32, SyncMessage)>,
+}
+
+impl ClientSyncResource {
+    /// Replaces the chunks a client watches, new chunks start with a snapshot
+    pub fn subscribe(&mut self, client_id: u32, chunk_keys: &Vec<i32>) {
+        let client = self.clients.entry(client_id).or_insert_with(ClientSync::default);
+        client.chunks.retain(|key, _| chunk_keys.contains(key));
+        for key in chunk_keys.iter() {
+            client.chunks.entry(*key).or_insert_with(ClientChunkSync::default);
+        }
+    }
+
+    pub fn unsubscribe(&mut self, client_id: u32) {
+        self.clients.remove(&client_id);
+    }
+
+    pub fn ack(&mut self, client_id: u32, seq: u32) {
+        if let Some(client) = self.clients.get_mut(&client_id) {
+            for chunk in client.chunks.values_mut() {
+                chunk.ack(seq);
+            }
+        }
+    }
+
+    /// Forgets everything a client acked, it gets fresh snapshots next update
+    pub fn resync(&mut self, client_id: u32) {
+        if let Some(client) = self.clients.get_mut(&client_id) {
+            for chunk in client.chunks.values_mut() {
+                chunk.reset();
+            }
+        }
+    }
+
+    /// Queues a tile change for every client watching the chunk
+    pub fn broadcast_tile(&mut self, frame: u32, chunk_key: i32, x: i32, y: i32, tile: u8) {
+        for (client_id, client) in self.clients.iter() {
+            if client.chunks.contains_key(&chunk_key) {
+                self.outbox.push((*client_id, SyncMessage::TileChanged{frame, chunk_key, x, y, tile}));
+            }
+        }
+    }
+
+    /// Queues a path failure for every client watching the chunk the unit stands in
+    pub fn broadcast_path_failed(&mut self, frame: u32, chunk_key: i32, id: u32, from: (i32, i32), to: (i32, i32), fallback: Option<(i32, i32)>) {
+        for (client_id, client) in self.clients.iter() {
+            if client.chunks.contains_key(&chunk_key) {
+                self.outbox.push((*client_id, SyncMessage::PathFailed{
+                    frame, chunk_key, id, sx: from.0, sy: from.1, tx: to.0, ty: to.1, fallback
+                }));
+            }
+        }
+    }
+
+    pub fn drain_outbox(&mut self) -> Vec<(u32, SyncMessage)> {
+        self.outbox.drain(..).collect()
+    }
+}
+
+fn diff_chunk_state(base: &ChunkState, current: &ChunkState) -> (Vec<(u32, SyncEntityState)>, Vec<u32>) {
+    let mut upserts = Vec::new();
+    for (id, state) in current.iter() {
+        if base.get(id) != Some(state) {
+            upserts.push((*id, *state));
+        }
+    }
+    let removes = base.keys()
+        .filter(|id| !current.contains_key(id))
+        .cloned()
+        .collect();
+    (upserts, removes)
+}
+
+/// Builds the state of every subscribed chunk and queues a snapshot or delta
+/// for each client whose view changed since the last message
+pub fn update_client_sync(world: &mut World, resources: &mut Resources) {
+    let frame = match resources.get::<GameConfigResource>() {
+        Some(conf) => conf.number_of_updates,
+        None => 0,
+    };
+
+    if let Some(mut sync) = resources.get_mut::<ClientSyncResource>() {
+        if sync.clients.len() == 0 {
+            return;
+        }
+
+        let mut watched_keys = HashSet::<i32>::new();
+        for client in sync.clients.values() {
+            watched_keys.extend(client.chunks.keys().cloned());
+        }
+
+        let mut chunk_states = HashMap::<i32, ChunkState>::new();
+        for key in watched_keys.iter() {
+            chunk_states.insert(*key, ChunkState::new());
+        }
+        let query = <(Read<LandPos>, Tagged<Model>)>::query();
+        for (entity, (pos, model)) in query.iter_entities_mut(world) {
+            if let Some(state) = chunk_states.get_mut(&pos.get_hash_map_key()) {
+                state.insert(entity.index(), SyncEntityState{model: model.0, x: pos.0, y: pos.1});
+            }
+        }
+
+        let ClientSyncResource { clients, outbox } = &mut *sync;
+        for (client_id, client) in clients.iter_mut() {
+            for (chunk_key, chunk) in client.chunks.iter_mut() {
+                let current = &chunk_states[chunk_key];
+                if chunk.last_sent() == Some(current) {
+                    continue;
+                }
+                if chunk.pending.len() >= MAX_PENDING_STATES {
+                    chunk.reset();
+                }
+
+                let seq = client.next_seq;
+                client.next_seq += 1;
+                match &chunk.acked {
+                    Some((base_seq, base)) => {
+                        let (upserts, removes) = diff_chunk_state(base, current);
+                        outbox.push((*client_id, SyncMessage::Delta {
+                            seq: seq,
+                            base_seq: *base_seq,
+                            frame: frame,
+                            chunk_key: *chunk_key,
+                            upserts: upserts,
+                            removes: removes,
+                        }));
+                    },
+                    None => {
+                        outbox.push((*client_id, SyncMessage::Snapshot {
+                            seq: seq,
+                            frame: frame,
+                            chunk_key: *chunk_key,
+                            entities: current.iter().map(|(id, state)| (*id, *state)).collect(),
+                        }));
+                    }
+                }
+                chunk.pending.push_back((seq, current.clone()));
+            }
+        }
+    }
+}
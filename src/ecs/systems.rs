@@ -2,26 +2,63 @@
 use super::*;
 use crate::ecs::types::{*};
 use crate::ecs::components::{*};
+use crate::ecs::submap::{TileMap};
+use crate::ecs::metrics::{MetricsResource, SimMetrics};
+use crate::ecs::pathing::{self, PathFallback, SharedPath};
+use crate::ecs::flow_field::{FlowFieldResource, FLOW_TRACE_STEPS};
+use crate::ecs::connectivity::{Connectivity, ConnectivityResource};
+use crate::ecs::parcel::{LandParcelResource};
+use crate::ecs::building::{BuildingsResource};
+use crate::ecs::sync::{ClientSyncResource};
 use astar::astar;
 use legion::prelude::*;
 use std::collections::HashMap;
+use std::sync::Arc;
 use rand::Rng;
 
+/// Tracing span and timer of one system run, both last until the end of the enclosing block
+macro_rules! enter_system {
+    ($metrics:expr, $name:expr) => {
+        let span = tracing::info_span!("system", name = $name);
+        let _enter = span.enter();
+        let _timer = $metrics.0.time_system($name);
+    };
+}
+
 pub fn build_update_chimera_spawners() -> Box<dyn Schedulable>  {
-    SystemBuilder::new("update_chimera_spawners")
+    const NAME: &str = "update_chimera_spawners";
+    SystemBuilder::new(NAME)
         .read_resource::<GameConfigResource>()
         .write_resource::<EmitEventResource>()
+        .read_resource::<TileMapResource>()
+        .read_resource::<MetricsResource>()
         .with_query(<(Read<LandPos>, Write<ChimeraSpawner>)>::query())
-        .build(move |command_buffer, mut world, (res0, res1), query| {
+        .build(move |command_buffer, mut world, (res0, res1, res2, metrics), query| {
+            enter_system!(metrics, NAME);
             let conf = &res0;
 
             let emit_event = &mut res1.0;
-            for (pos, mut spawner) in query.iter_mut(&mut world) {
+            let tm = &res2.0;
+            let mut rng = rand::thread_rng();
+            for (spawner_pos, mut spawner) in query.iter_mut(&mut world) {
                 spawner.tick_ms += conf.fixed_time_ms as i32;
                 if spawner.tick_ms >= spawner.cooldown_ms {
                     spawner.tick_ms -= spawner.cooldown_ms;
                     //println!("spawn chimera {:?} - {:?}", spawner.tick_ms, dt_ms);
 
+                    let mut pos = *spawner_pos;
+                    if spawner.w > 0 && spawner.h > 0 {
+                        // a few tries for a reachable tile, the spawner position otherwise
+                        for _ in 0..8 {
+                            let x = rng.gen_range(spawner.x, spawner.x + spawner.w);
+                            let y = rng.gen_range(spawner.y, spawner.y + spawner.h);
+                            if x >= 1 && y >= 1 && x < tm.w as i32 - 1 && y < tm.h as i32 - 1 && tm.can_move_to(&(x, y)) {
+                                pos = LandPos(x, y);
+                                break;
+                            }
+                        }
+                    }
+
                     let entities: &[Entity] = command_buffer.insert(
                         ((Model(UnitModelType::Chimera as u32)), Chimera),
                         vec![
@@ -42,11 +79,14 @@ pub fn build_update_chimera_spawners() -> Box<dyn Schedulable>  {
 }
 
 pub fn build_update_moving() -> Box<dyn Schedulable>  {
-    SystemBuilder::new("update_moving")
+    const NAME: &str = "update_moving";
+    SystemBuilder::new(NAME)
         .read_resource::<GameConfigResource>()
+        .read_resource::<MetricsResource>()
         .with_query(<(Read<LandPos>, Write<Moving>)>::query()
             .filter(!component::<NewPos>()))
-        .build(move |command_buffer, mut world, (res0), query| {
+        .build(move |command_buffer, mut world, (res0, metrics), query| {
+            enter_system!(metrics, NAME);
             //res1.0 = res2.0.clone(); // Write the mutable resource from the immutable resource
             //let dt_time = res0.fixed_time_ms  as f64 * 0.001;
             for (mut entity, (pos, mut mv)) in query.iter_entities_mut(&mut world) {
@@ -54,7 +94,7 @@ pub fn build_update_moving() -> Box<dyn Schedulable>  {
                 if mv.vx == 0 && mv.vy == 0 {
                     continue;
                 }
-                mv.step += mv.speed * res0.fixed_time_ms / mv.cost as u64;
+                mv.step += mv.step_per_update(res0.fixed_time_ms);
                 if mv.step >= mv.maxstep {
                     command_buffer.add_component(entity, NewPos(pos.0 + mv.vx, pos.1 + mv.vy));
                     // pos.0 += mv.vx;
@@ -71,11 +111,14 @@ pub fn build_update_moving() -> Box<dyn Schedulable>  {
 }
 
 pub fn build_update_new_pos() -> Box<dyn Schedulable>  {
-    SystemBuilder::new("update_moving")
+    const NAME: &str = "update_new_pos";
+    SystemBuilder::new(NAME)
         .read_resource::<GameConfigResource>()
         .write_resource::<EmitEventResource>()
+        .read_resource::<MetricsResource>()
         .with_query(<(Write<LandPos>, Read<NewPos>)>::query())
-        .build(move |command_buffer, mut world, (res0, res1), query| {
+        .build(move |command_buffer, mut world, (res0, res1, metrics), query| {
+            enter_system!(metrics, NAME);
             let conf = &res0;
             let emit_event = &mut res1.0;
             for (mut entity, (mut pos, newpos)) in query.iter_entities_mut(&mut world) {
@@ -96,101 +139,289 @@ pub fn build_update_new_pos() -> Box<dyn Schedulable>  {
         })
 }
 
+/// Walks `paths` from `current_index` while direction and tile cost stay the same as `mv`,
+/// returns the index where that segment ends and the frames needed to reach it.
+/// The unit stops one step away from `goal`, so does the segment.
+fn estimate_move_segment(tm: &TileMap, paths: &Vec<(i32, i32)>, current_index: usize, goal: &(i32, i32), mv: &Moving, fixed_time_ms: u64) -> (usize, u64) {
+    let mut index = current_index;
+    let mut step = mv.step;
+    let mut frames = 0;
+    while index + 1 < paths.len() {
+        if index > current_index && tm.within_one_step(&paths[index], goal) {
+            break;
+        }
+        let vx = paths[index+1].0 - paths[index].0;
+        let vy = paths[index+1].1 - paths[index].1;
+        let cost = tm.get_move_cost(&paths[index+1]);
+        if vx != mv.vx || vy != mv.vy || cost != mv.cost {
+            break;
+        }
+        if index > current_index {
+            // NewPos of the previous step is applied one frame before the next step starts
+            frames += 1;
+        }
+        let mut seg_mv = *mv;
+        seg_mv.cost = cost;
+        loop {
+            step += seg_mv.step_per_update(fixed_time_ms);
+            frames += 1;
+            if step >= mv.maxstep {
+                break;
+            }
+        }
+        step -= mv.maxstep;
+        index += 1;
+    }
+    (index, frames)
+}
+
+fn start_next_step(
+    entity: &Entity,
+    tm: &TileMap,
+    paths: &Vec<(i32, i32)>,
+    current_index: usize,
+    goal: &(i32, i32),
+    mv: &mut Moving,
+    conf: &GameConfigResource,
+    emit_event: &mut Vec<(i32, LunaciaWorldEvent)>,
+) {
+    mv.vx = paths[current_index+1].0 - paths[current_index].0;
+    mv.vy = paths[current_index+1].1 - paths[current_index].1;
+    mv.cost = tm.get_move_cost(&paths[current_index+1]);
+    mv.maxstep = tm.step_length(mv.vx, mv.vy);
+
+    if mv.seg_steps == 0 {
+        let (end_index, frames) = estimate_move_segment(tm, paths, current_index, goal, mv, conf.fixed_time_ms);
+        mv.seg_steps = (end_index - current_index) as u32;
+
+        let start_pos = LandPos(paths[current_index].0, paths[current_index].1);
+        emit_event.push((start_pos.get_hash_map_key(), LunaciaWorldEvent::EventMoveSegment{
+            frame: conf.number_of_updates,
+            id: entity.index(),
+            sx: start_pos.0,
+            sy: start_pos.1,
+            tx: paths[end_index].0,
+            ty: paths[end_index].1,
+            vx: mv.vx,
+            vy: mv.vy,
+            arrive_frame: conf.number_of_updates + frames as u32,
+        }));
+    }
+    mv.seg_steps -= 1;
+}
+
+/// Path from `pos` to the target of `fp`, from the cache or a new search.
+/// The unit starts over from `pos`, which becomes the start of `fp`.
+/// No search runs for a target out of its connected region.
+fn plan_path(
+    tm: &TileMap,
+    conn: &Connectivity,
+    fp: &mut FollowPath,
+    pos: &LandPos,
+    conf: &GameConfigResource,
+    pw: &mut HashMap<((i32, i32), (i32, i32)), SharedPath>,
+    metrics: &SimMetrics,
+) -> Option<SharedPath> {
+    fp.sx = pos.0;
+    fp.sy = pos.1;
+    let pathway_key = ((fp.sx, fp.sy), (fp.tx, fp.ty));
+    if let Some(paths) = pw.get(&pathway_key) {
+        return Some(paths.clone());
+    }
+    if !conn.reachable(&(pos.0, pos.1), &(fp.tx, fp.ty)) {
+        metrics.observe_search_skipped();
+        return None;
+    }
+    let search = pathing::find_path(tm, &(pos.0, pos.1), &(fp.tx, fp.ty), conf.path_algorithm, &conf.path_search);
+    metrics.observe_astar(search.expanded, search.path.is_some());
+    if search.cut_off() {
+        metrics.observe_search_cut_off();
+    }
+    let (paths, _cost) = search.path?;
+    //println!("{:?},{:?} -> {:?},{:?} Path found length: {:?}. cost: {:?}", pos.0, pos.1, fp.tx, fp.ty, paths.len(), _cost);
+    let paths = Arc::new(paths);
+    pw.insert(pathway_key, paths.clone());
+    Some(paths)
+}
+
+/// Starts the first step of `paths` and returns the cursor walking it
+fn start_path(
+    entity: &Entity,
+    tm: &TileMap,
+    paths: SharedPath,
+    goal: &(i32, i32),
+    mv: &mut Moving,
+    conf: &GameConfigResource,
+    emit_event: &mut Vec<(i32, LunaciaWorldEvent)>,
+) -> PathCursor {
+    start_next_step(entity, tm, &paths, 0, goal, mv, conf, emit_event);
+    PathCursor { path: paths, index: 1 }
+}
+
+/// Retries the search of a unit later, or gives up its target: it gets `PathFailed`
+/// so its goal is dropped once it stands still, and the path to the nearest reachable
+/// tile is returned when the policy allows
+fn fail_path(
+    entity: &Entity,
+    tm: &TileMap,
+    fp: &mut FollowPath,
+    pos: &LandPos,
+    conf: &GameConfigResource,
+    pw: &mut HashMap<((i32, i32), (i32, i32)), SharedPath>,
+    emit_event: &mut Vec<(i32, LunaciaWorldEvent)>,
+    sync: &mut ClientSyncResource,
+    command_buffer: &mut CommandBuffer,
+) -> Option<SharedPath> {
+    let policy = &conf.path_failure;
+    if fp.attempts < policy.retries {
+        fp.attempts += 1;
+        fp.retry_frame = conf.number_of_updates + policy.retry_delay_frames(fp.attempts, conf.fixed_time_ms);
+        return None;
+    }
+
+    let fallback = match policy.fallback {
+        PathFallback::Nearest => pathing::nearest_reachable(tm, &(pos.0, pos.1), &(fp.tx, fp.ty), &conf.path_search),
+        PathFallback::None => None,
+    };
+    let end = fallback.as_ref().map(|(paths, _)| paths[paths.len() - 1]);
+    emit_event.push((pos.get_hash_map_key(), LunaciaWorldEvent::EventPathFailed{
+        frame: conf.number_of_updates,
+        id: entity.index(),
+        sx: pos.0,
+        sy: pos.1,
+        tx: fp.tx,
+        ty: fp.ty,
+        fallback: end,
+    }));
+    sync.broadcast_path_failed(conf.number_of_updates, pos.get_hash_map_key(), entity.index(), (pos.0, pos.1), (fp.tx, fp.ty), end);
+    command_buffer.add_component(*entity, PathFailed);
+
+    match fallback {
+        Some((paths, _)) => {
+            let end = paths[paths.len() - 1];
+            *fp = FollowPath::new(pos.0, pos.1, end.0, end.1);
+            let paths = Arc::new(paths);
+            pw.insert(((fp.sx, fp.sy), (fp.tx, fp.ty)), paths.clone());
+            Some(paths)
+        }
+        None => {
+            command_buffer.remove_component::<FollowPath>(*entity);
+            command_buffer.remove_component::<Moving>(*entity);
+            None
+        }
+    }
+}
+
 pub fn build_update_follow_paths() -> Box<dyn Schedulable>  {
-    SystemBuilder::new("update_follow_paths")
+    const NAME: &str = "update_follow_paths";
+    SystemBuilder::new(NAME)
         .read_resource::<TileMapResource>()
+        .read_resource::<GameConfigResource>()
         .write_resource::<PathwayHashMapResource>()
-        .with_query(<(Read<FollowPath>, Read<LandPos>, Write<Moving>)>::query()
+        .write_resource::<EmitEventResource>()
+        .read_resource::<MetricsResource>()
+        .write_resource::<FlowFieldResource>()
+        .read_resource::<ConnectivityResource>()
+        .write_resource::<ClientSyncResource>()
+        .with_query(<(Write<FollowPath>, Read<LandPos>, Write<Moving>, Write<PathCursor>)>::query()
             .filter(!component::<NewPos>()))
-        .build(move |command_buffer, mut world, (res0, res1), query| {
+        .with_query(<(Write<FollowPath>, Read<LandPos>, Write<Moving>)>::query()
+            .filter(!component::<NewPos>() & !component::<PathCursor>()))
+        .build(move |command_buffer, mut world, (res0, res1, res2, res3, metrics, res4, res5, res6), (following, planning)| {
+            enter_system!(metrics, NAME);
             let tm = &res0.0;
-            let pw = &mut res1.0;
-            
-            for (mut entity, (fp, pos, mut mv)) in query.iter_entities_mut(&mut world) {
-            //for (fp, pos, mut mv) in query.iter_mut(&mut world) {
-                if mv.vx == 0 && mv.vy == 0 {
-                    if !tm.can_move_to(&(fp.tx, fp.ty)) {
-                        //println!("can_t_move_to {:?} {:?}", fp.tx, fp.ty);
-                        command_buffer.remove_component::<FollowPath>(entity);
-                        command_buffer.remove_component::<Moving>(entity);
-                    } else if pos.distance(&LandPos(fp.tx, fp.ty)) <= 1{
-                        // if entity.index() == 575 {
-                        //     println!("{:?} {:?},{:?} -> {:?},{:?} Reach target", entity.index(), pos.0, pos.1, fp.tx, fp.ty);
-                        // }
+            let conf = &res1;
+            let pw = &mut res2.0;
+            let emit_event = &mut res3.0;
+            let flow = &mut res4.0;
+            let conn = &res5.0;
+            let sync: &mut ClientSyncResource = res6;
+
+            // units walking a path, their cursor gives the next step
+            for (entity, (mut fp, pos, mut mv, mut cursor)) in following.iter_entities_mut(&mut world) {
+                if mv.vx != 0 || mv.vy != 0 {
+                    continue;
+                }
+                if tm.within_one_step(&(pos.0, pos.1), &(fp.tx, fp.ty)) {
+                    command_buffer.remove_component::<FollowPath>(entity);
+                    command_buffer.remove_component::<Moving>(entity);
+                    command_buffer.remove_component::<PathCursor>(entity);
+                    continue;
+                }
+                if cursor.is_done() || cursor.current() != Some((pos.0, pos.1)) {
+                    // pushed off its path, plan again from where it stands
+                    let replanned = match plan_path(tm, conn, &mut fp, &pos, conf, pw, &metrics.0) {
+                        Some(paths) => Some(paths),
+                        None => fail_path(&entity, tm, &mut fp, &pos, conf, pw, emit_event, sync, command_buffer),
+                    };
+                    match replanned {
+                        Some(paths) => *cursor = PathCursor::new(paths),
+                        None => {
+                            command_buffer.remove_component::<PathCursor>(entity);
+                            continue;
+                        }
+                    }
+                }
+                start_next_step(&entity, tm, &cursor.path, cursor.index, &(fp.tx, fp.ty), &mut mv, conf, emit_event);
+                cursor.index += 1;
+            }
+
+            // units per destination, busy ones get a flow field instead of their own paths
+            let mut demand: HashMap<(i32, i32), u32> = HashMap::new();
+            for (fp, _, _, _) in following.iter_mut(&mut world) {
+                *demand.entry((fp.tx, fp.ty)).or_insert(0) += 1;
+            }
+            for (fp, _, _) in planning.iter_mut(&mut world) {
+                *demand.entry((fp.tx, fp.ty)).or_insert(0) += 1;
+            }
+
+            // units without a path yet, or waiting to retry
+            for (entity, (mut fp, pos, mut mv)) in planning.iter_entities_mut(&mut world) {
+                if mv.vx != 0 || mv.vy != 0 || fp.retry_frame > conf.number_of_updates {
+                    continue;
+                }
+                let goal: (i32, i32) = (fp.tx, fp.ty);
+                let mut paths = None;
+                if tm.can_move_to(&goal) {
+                    if tm.within_one_step(&(pos.0, pos.1), &(fp.tx, fp.ty)) {
                         command_buffer.remove_component::<FollowPath>(entity);
                         command_buffer.remove_component::<Moving>(entity);
-                    } else {
-                        let goal: (i32, i32) = (fp.tx, fp.ty);
-                        let pathway_key = ((fp.sx, fp.sy), (fp.tx, fp.ty));
-
-                        
-
-                        match pw.get(&pathway_key) {
-                            Some(paths) => {
-                                let mut current_index = 0;
-                                while current_index < paths.len() {
-                                    if paths[current_index] == (pos.0, pos.1) {
-                                        break;
-                                    }
-                                    current_index += 1;
-                                }
+                        continue;
+                    }
 
-                                if current_index < paths.len() - 1 {
-                                    mv.vx = paths[current_index+1].0 - paths[current_index].0;
-                                    mv.vy = paths[current_index+1].1 - paths[current_index].1;
-
-                                    // if entity.index() == 575 {
-                                    //     println!("{:?} check... {:?} {:?} - vel {:?} {:?}", entity.index(), pos.0, pos.1, mv.vx, mv.vy);
-                                    // }
-                                    mv.cost = tm.get_move_cost(&paths[current_index+1]);
-                                    mv.maxstep = 1000;
-                                } else {
-                                    println!("Invalid pathway cache {:?} {:?},{:?} -> {:?},{:?} : {:?},{:?}", entity.index(), fp.sx, fp.sy, fp.tx, fp.ty, pos.0, pos.1);
-                                    // for path in paths.iter() {
-                                    //     println!("----{:?},{:?}", path.0, path.1);
-                                    // }
-                                    command_buffer.remove_component::<FollowPath>(entity);
-                                    command_buffer.remove_component::<Moving>(entity);
-                                }
-                            },
-                            None => {
-                                let result = astar::astar(&(pos.0, pos.1),
-                                    |&p| tm.successors(&p),
-                                    |&(x, y)| (x - goal.0).abs() as u32 + (y - goal.1).abs() as u32,
-                                    |&p| p == goal);
-                                match result {
-                                    Some((paths, cost)) => {
-                                        //println!("{:?},{:?} -> {:?},{:?} Path found length: {:?}. cost: {:?}", pos.0, pos.1, goal.0, goal.1, paths.len(), cost);
-
-                                        mv.vx = paths[1].0 - paths[0].0;
-                                        mv.vy = paths[1].1 - paths[0].1;
-                                        mv.cost = tm.get_move_cost(&paths[1]);
-                                        mv.maxstep = 1000;
-
-                                        pw.insert(pathway_key, paths);
-                                    }
-                                    None => {
-                                        println!("No path found {:?},{:?} -> {:?},{:?}", pos.0, pos.1, fp.tx, fp.ty);
-                                        command_buffer.remove_component::<FollowPath>(entity);
-                                        command_buffer.remove_component::<Moving>(entity);
-                                    }
-                                }
-                            }
+                    let goal_demand = demand.get(&goal).cloned().unwrap_or(0);
+                    if flow.ensure(tm, goal, goal_demand, conf.number_of_updates).is_some() {
+                        metrics.0.observe_flow_field();
+                    }
+                    if let Some(field) = flow.get(&goal) {
+                        let steps = field.trace(tm, &(pos.0, pos.1), FLOW_TRACE_STEPS);
+                        if steps.len() > 1 {
+                            start_next_step(&entity, tm, &steps, 0, &goal, &mut mv, conf, emit_event);
+                            continue;
                         }
+                    } else {
+                        paths = plan_path(tm, conn, &mut fp, &pos, conf, pw, &metrics.0);
                     }
-                } else {
-
+                }
+                if paths.is_none() {
+                    paths = fail_path(&entity, tm, &mut fp, &pos, conf, pw, emit_event, sync, command_buffer);
+                }
+                if let Some(paths) = paths {
+                    command_buffer.add_component(entity, start_path(&entity, tm, paths, &(fp.tx, fp.ty), &mut mv, conf, emit_event));
                 }
             }
         })
 }
 
 pub fn build_set_quadrant_data_hash_map() -> Box<dyn Schedulable>  {
-    SystemBuilder::new("set_quadrant_data_hash_map")
+    const NAME: &str = "set_quadrant_data_hash_map";
+    SystemBuilder::new(NAME)
         .write_resource::<QuadrantDataHashMapResource>()
+        .read_resource::<MetricsResource>()
         .with_query(<(Read<LandPos>, Tagged<Model>)>::query()
             .filter(!component::<QuadrantKey>()))
-        .build(move |command_buffer, mut world, (conf), query| {
+        .build(move |command_buffer, mut world, (conf, metrics), query| {
+            enter_system!(metrics, NAME);
             let hm = &mut conf.0;
         
             for (mut entity, (pos, model)) in query.iter_entities_mut(&mut world) {
@@ -207,11 +438,14 @@ pub fn build_set_quadrant_data_hash_map() -> Box<dyn Schedulable>  {
 }
 
 pub fn build_gather_resource_goals() -> Box<dyn Schedulable>  {
-    SystemBuilder::new("gather_resource_goals")
+    const NAME: &str = "gather_resource_goals";
+    SystemBuilder::new(NAME)
         .read_resource::<TileMapResource>()
+        .read_resource::<MetricsResource>()
         .with_query(<(Write<GatherResourceGoal>, Read<LandPos>)>::query()
-            .filter(!component::<GAction>() & !component::<GAction>()))
-        .build(move |command_buffer, mut world, (res0), query| {
+            .filter(!component::<GAction>() & !component::<GAction>() & !component::<PathFailed>()))
+        .build(move |command_buffer, mut world, (res0, metrics), query| {
+            enter_system!(metrics, NAME);
              
             for (mut entity, (mut goal, pos)) in query.iter_entities_mut(&mut world) {
                 match goal.step {
@@ -220,7 +454,7 @@ pub fn build_gather_resource_goals() -> Box<dyn Schedulable>  {
                         //     println!("{:?} Will GActionGatherResource", entity.index());
                         // }
                         goal.step += 1;
-                        command_buffer.add_component(entity, FollowPath {sx: pos.0, sy: pos.1, tx: goal.target_pos.0, ty: goal.target_pos.1});
+                        command_buffer.add_component(entity, FollowPath::new(pos.0, pos.1, goal.target_pos.0, goal.target_pos.1));
                         command_buffer.add_component(entity, Moving::new());
                         command_buffer.add_component(entity, GAction::new_gather_resource_action());
                         command_buffer.add_tag(entity, GActionGatherResource);
@@ -230,7 +464,7 @@ pub fn build_gather_resource_goals() -> Box<dyn Schedulable>  {
                         //     println!("{:?} Will GActionReleaseResource", entity.index());
                         // }
                         goal.step += 1;
-                        command_buffer.add_component(entity, FollowPath {sx: pos.0, sy: pos.1, tx: goal.home_pos.0, ty: goal.home_pos.1});
+                        command_buffer.add_component(entity, FollowPath::new(pos.0, pos.1, goal.home_pos.0, goal.home_pos.1));
                         command_buffer.add_component(entity, Moving::new());
                         command_buffer.add_component(entity, GAction::new_release_resource_action());
                         command_buffer.add_tag(entity, GActionReleaseResource);
@@ -249,11 +483,14 @@ pub fn build_gather_resource_goals() -> Box<dyn Schedulable>  {
 }
 
 pub fn build_gather_resource_actions() -> Box<dyn Schedulable>  {
-    SystemBuilder::new("build_gather_resource_actions")
+    const NAME: &str = "build_gather_resource_actions";
+    SystemBuilder::new(NAME)
         .read_resource::<TileMapResource>()
+        .read_resource::<MetricsResource>()
         .with_query(<(Write<GAction>)>::query()
-            .filter(!component::<Moving>() & tag::<GActionGatherResource>()))
-        .build(move |command_buffer, mut world, (res0), query| {
+            .filter(!component::<Moving>() & !component::<PathFailed>() & tag::<GActionGatherResource>()))
+        .build(move |command_buffer, mut world, (res0, metrics), query| {
+            enter_system!(metrics, NAME);
              
             for (mut entity, (mut action)) in query.iter_entities_mut(&mut world) {
                 command_buffer.remove_tag::<GActionGatherResource>(entity);
@@ -262,13 +499,34 @@ pub fn build_gather_resource_actions() -> Box<dyn Schedulable>  {
         })
 }
 
+/// Units back home hand their resource to a storage of their owner on the parcel of
+/// their home. Paths end next to the home tile, so the parcel is the one of the goal.
 pub fn build_release_resource_actions() -> Box<dyn Schedulable>  {
-    SystemBuilder::new("build_release_resource_actions")
+    const NAME: &str = "build_release_resource_actions";
+    SystemBuilder::new(NAME)
         .read_resource::<TileMapResource>()
+        .read_resource::<MetricsResource>()
+        .read_resource::<LandParcelResource>()
+        .write_resource::<BuildingsResource>()
         .with_query(<(Write<GAction>)>::query()
-            .filter(!component::<Moving>() & tag::<GActionReleaseResource>()))
-        .build(move |command_buffer, mut world, (res0), query| {
-             
+            .filter(!component::<Moving>() & !component::<PathFailed>() & tag::<GActionReleaseResource>()))
+        .with_query(<(Read<LandPos>, Read<GatherResourceGoal>, Tagged<Owner>)>::query()
+            .filter(component::<GAction>() & !component::<Moving>() & !component::<PathFailed>() & tag::<GActionReleaseResource>()))
+        .build(move |command_buffer, mut world, (res0, metrics, res1, res2), (query, deliveries)| {
+            enter_system!(metrics, NAME);
+            let tm = &res0.0;
+            let parcels = &res1.0;
+            let buildings = &mut res2.0;
+
+            for (pos, goal, owner) in deliveries.iter_mut(&mut world) {
+                let home = goal.home_pos;
+                let stored = if tm.within_one_step(&(pos.0, pos.1), &(home.0, home.1)) {
+                    buildings.deliver(parcels, owner.0, &(home.0, home.1), 1)
+                } else {
+                    None
+                };
+                metrics.0.observe_delivery(stored.is_some());
+            }
             for (mut entity, (mut action)) in query.iter_entities_mut(&mut world) {
                 command_buffer.remove_tag::<GActionReleaseResource>(entity);
                 command_buffer.remove_component::<GAction>(entity);
@@ -276,10 +534,77 @@ pub fn build_release_resource_actions() -> Box<dyn Schedulable>  {
         })
 }
 
+/// Moves construction forward by one update and announces the finished buildings
+pub fn build_update_constructions() -> Box<dyn Schedulable>  {
+    const NAME: &str = "update_constructions";
+    SystemBuilder::new(NAME)
+        .read_resource::<GameConfigResource>()
+        .write_resource::<EmitEventResource>()
+        .write_resource::<BuildingsResource>()
+        .read_resource::<MetricsResource>()
+        .build(move |_, _, (res0, res1, res2, metrics), _| {
+            enter_system!(metrics, NAME);
+            let conf = &res0;
+            let emit_event = &mut res1.0;
+            let buildings = &mut res2.0;
+
+            for id in buildings.advance(conf.fixed_time_ms as u32) {
+                if let Some(b) = buildings.get(id) {
+                    emit_event.push((LandPos(b.x, b.y).get_hash_map_key(), LunaciaWorldEvent::EventBuildingCompleted{
+                        frame: conf.number_of_updates,
+                        id: id,
+                        owner: b.owner,
+                        model: b.kind.model(),
+                        tx: b.x,
+                        ty: b.y,
+                    }));
+                }
+            }
+        })
+}
+
+/// Rolls back the goal of the units that gave up a path once they stand still:
+/// the pending action is dropped with the goal, so nothing runs as if they arrived
+pub fn build_path_failures() -> Box<dyn Schedulable>  {
+    const NAME: &str = "path_failures";
+    SystemBuilder::new(NAME)
+        .read_resource::<MetricsResource>()
+        .with_query(<(Read<PathFailed>)>::query()
+            .filter(!component::<FollowPath>() & tag::<GActionGatherResource>()))
+        .with_query(<(Read<PathFailed>)>::query()
+            .filter(!component::<FollowPath>() & tag::<GActionReleaseResource>()))
+        .with_query(<(Read<PathFailed>, Read<GatherResourceGoal>)>::query()
+            .filter(!component::<FollowPath>()))
+        .with_query(<(Read<PathFailed>)>::query()
+            .filter(!component::<FollowPath>()))
+        .build(move |command_buffer, mut world, (metrics), (gather, release, goals, failed)| {
+            enter_system!(metrics, NAME);
+
+            for (entity, _) in gather.iter_entities_mut(&mut world) {
+                command_buffer.remove_tag::<GActionGatherResource>(entity);
+                command_buffer.remove_component::<GAction>(entity);
+            }
+            for (entity, _) in release.iter_entities_mut(&mut world) {
+                command_buffer.remove_tag::<GActionReleaseResource>(entity);
+                command_buffer.remove_component::<GAction>(entity);
+            }
+            for (entity, _) in goals.iter_entities_mut(&mut world) {
+                command_buffer.remove_component::<GatherResourceGoal>(entity);
+                command_buffer.remove_tag::<GGoal>(entity);
+            }
+            for (entity, _) in failed.iter_entities_mut(&mut world) {
+                command_buffer.remove_component::<PathFailed>(entity);
+            }
+        })
+}
+
 pub fn build_player_input_cleans() -> Box<dyn Schedulable>  {
-    SystemBuilder::new("build_player_input_cleans")
+    const NAME: &str = "build_player_input_cleans";
+    SystemBuilder::new(NAME)
+        .read_resource::<MetricsResource>()
         .with_query(<(Read<PlayerInput>)>::query())
-        .build(move |command_buffer, mut world, (res0), query| {
+        .build(move |command_buffer, mut world, (metrics), query| {
+            enter_system!(metrics, NAME);
             for (mut entity, (pi)) in query.iter_entities_mut(&mut world) {
                 match &pi.status {
                     1 => {
@@ -292,28 +617,39 @@ pub fn build_player_input_cleans() -> Box<dyn Schedulable>  {
 }
 
 pub fn build_auto_collect_resources() -> Box<dyn Schedulable>  {
-    SystemBuilder::new("build_auto_collect_resources")
+    const NAME: &str = "build_auto_collect_resources";
+    SystemBuilder::new(NAME)
         .read_resource::<TileMapResource>()
         .read_resource::<QuadrantDataHashMapResource>()
+        .read_resource::<GameConfigResource>()
+        .read_resource::<MetricsResource>()
+        .read_resource::<ConnectivityResource>()
         .with_query(<(Write<HomeLand>)>::query()
             .filter(tag::<AutoCollect>() & !tag::<GGoal>()))
-        .build(move |command_buffer, mut world, (res0, res1), query| {
+        .build(move |command_buffer, mut world, (res0, res1, res2, metrics, res3), query| {
+            enter_system!(metrics, NAME);
+            let tm = &res0.0;
             let hm = &res1.0;
+            let conf = &res2;
+            let conn = &res3.0;
+            let mut options = conf.path_search;
+            if conf.auto_collect_max_cost > 0 {
+                options.max_cost = Some(conf.auto_collect_max_cost);
+            }
 
             let mut rng = rand::thread_rng();
 
             for (mut entity, (mut hl)) in query.iter_entities_mut(&mut world) {
                 let n: u32 = rng.gen_range(0, 100);
-                if n > 10 {
+                if n >= conf.auto_collect_chance {
                     continue;
                 }
                 let home_pos = hl.0;
 
                 let mut search_range = 1;
                 let search_model = BuildingModelType::ResourceNode as u32;
-                let mut nearest_distance = -1;
-                let mut target_pos : Option<LandPos> = None;
-                while search_range < 10 {
+                let mut candidates: Vec<(i32, i32)> = Vec::new();
+                while search_range < conf.auto_collect_search_range {
                     let visible_chunk_keys = home_pos.get_hash_map_key_successors_at_radius(search_range);
                     for chunk_key in visible_chunk_keys.iter() {
                         match hm.get(chunk_key) {
@@ -321,11 +657,8 @@ pub fn build_auto_collect_resources() -> Box<dyn Schedulable>  {
                                 match chunk.get(&search_model) {
                                     Some(objs) => {
                                         for (_, qd) in objs.iter() {
-                                            let check_pos = LandPos(qd.land_pos.0, qd.land_pos.1);
-                                            let dist = home_pos.distance(&check_pos);
-                                            if nearest_distance == -1 || dist < nearest_distance as u32 {
-                                                nearest_distance = dist as i32;
-                                                target_pos = Some(check_pos);
+                                            if conn.reachable(&(home_pos.0, home_pos.1), &qd.land_pos) {
+                                                candidates.push(qd.land_pos);
                                             }
                                         }
                                     },
@@ -338,8 +671,13 @@ pub fn build_auto_collect_resources() -> Box<dyn Schedulable>  {
                     }
                     search_range += 1;
                 }
-                
-                match target_pos {
+
+                // the closest node by road, not by straight distance
+                let search = pathing::nearest_target(tm, &(home_pos.0, home_pos.1), &candidates, &options);
+                if !candidates.is_empty() {
+                    metrics.0.observe_astar(search.expanded, search.path.is_some());
+                }
+                match search.path.map(|(paths, _)| LandPos(paths[paths.len() - 1].0, paths[paths.len() - 1].1)) {
                     Some(p) => {
                         command_buffer.add_tag(entity, GGoal);
                         command_buffer.add_component(entity, GatherResourceGoal{
@@ -1,6 +1,8 @@
 use super::*;
 // use crate::submap::{TileMap};
 use crate::ecs::submap::{*};
+use crate::ecs::pathing::{PathAlgorithm, PathFailurePolicy, SharedPath};
+use crate::ecs::astar::{SearchOptions};
 use std::collections::HashMap;
 use std::collections::VecDeque;
 use legion::prelude::{Entity};
@@ -11,6 +13,13 @@ pub struct GameConfigResource {
     pub fixed_time_ms: u64,
     pub map_width: usize,
     pub map_height: usize,
+    pub auto_collect_chance: u32,
+    pub auto_collect_search_range: u32,
+    pub auto_collect_max_cost: u32,
+    pub path_algorithm: PathAlgorithm,
+    pub path_failure: PathFailurePolicy,
+    pub path_search: SearchOptions<u32>,
+    pub render_dir: String,
     pub tmp_focusing_pos: (i32, i32)
 }
 
@@ -32,10 +41,11 @@ pub struct QuadrantDataHashMapResource(
 );
 
 #[derive(Clone)]
+/// Paths keyed by their first tile and target
 pub struct PathwayHashMapResource(
     pub HashMap <
         ((i32, i32), (i32, i32)), 
-        Vec<(i32, i32)>
+        SharedPath
     >
 );
 
@@ -71,7 +81,58 @@ pub enum LunaciaWorldEvent {
         id: u32,
         tx: i32,
         ty: i32,
-    }
+    },
+    /// Sent when a unit starts a straight run of same-cost tiles,
+    /// `arrive_frame` is the frame of the EventRelocation at (tx, ty)
+    EventMoveSegment {
+        frame: u32,
+        id: u32,
+        sx: i32,
+        sy: i32,
+        tx: i32,
+        ty: i32,
+        vx: i32,
+        vy: i32,
+        arrive_frame: u32,
+    },
+    /// A tile was edited, `tile` is its new value
+    EventTileChanged {
+        frame: u32,
+        tx: i32,
+        ty: i32,
+        tile: u8,
+    },
+    /// No path from (sx, sy) reaches (tx, ty), the unit gave up its goal.
+    /// `fallback` is the tile it walks to instead, if any.
+    EventPathFailed {
+        frame: u32,
+        id: u32,
+        sx: i32,
+        sy: i32,
+        tx: i32,
+        ty: i32,
+        fallback: Option<(i32, i32)>,
+    },
+    /// Construction of building `id` started, (tx, ty) is the top left tile of the footprint
+    EventBuildingPlaced {
+        frame: u32,
+        id: u32,
+        owner: u32,
+        model: u32,
+        tx: i32,
+        ty: i32,
+        w: i32,
+        h: i32,
+        done_frame: u32,
+    },
+    EventBuildingCompleted {
+        frame: u32,
+        id: u32,
+        owner: u32,
+        model: u32,
+        tx: i32,
+        ty: i32,
+    },
 }
 
 
@@ -1,15 +1,23 @@
 use actix::prelude::*;
 
 use super::*;
-// use lunacia_world::{LunaciaWorldActor, PingWorld, WorldPong, StartWorld};
+use crate::commands::{CommandRegistry, Dispatch};
+use crate::world_manager::{self, RouteCommand, RouteSync, WorldManager};
+use crate::lunacia_world::{SyncCommand, SyncRequest};
+
+/// Sync client id of the console, its sync messages are printed
+pub const CONSOLE_SYNC_CLIENT: u32 = 0;
 
 #[derive(Default)]
-pub struct IOWorldActior;
+pub struct IOWorldActior {
+   registry: CommandRegistry,
+}
 
 impl actix::Supervised for IOWorldActior {}
 
 impl ArbiterService for IOWorldActior {
-   fn service_started(&mut self, ctx: &mut Context<Self>) {
+   fn service_started(&mut self, _ctx: &mut Context<Self>) {
+        self.registry = CommandRegistry::with_builtins();
         println!("IOWorldActior Service started");
    }
 }
@@ -18,27 +26,64 @@ impl Actor for IOWorldActior {
    type Context = Context<Self>;
 
    fn started(&mut self, _: &mut Context<Self>) {
-      // get LunaciaWorldActor address from the registry
-      let act = LunaciaWorldActor::from_registry();
-      act.do_send(StartWorld);
    }
 }
 
 impl Handler<PingWorld> for IOWorldActior {
     type Result = ();
  
-    fn handle(&mut self, msg: PingWorld, ctx: &mut Context<Self>) {
-         println!("Request PingWorld");
-
-         let act = LunaciaWorldActor::from_registry();
-         act.do_send(PingWorld{data: msg.data});
+    fn handle(&mut self, msg: PingWorld, _ctx: &mut Context<Self>) {
+         if msg.data.trim().is_empty() {
+            return;
+         }
+         let (world, line) = world_manager::split_world_prefix(&msg.data);
+         let manager = WorldManager::from_registry();
+         match self.registry.dispatch(line) {
+            Ok(Dispatch::Reply(text)) => println!("{}", text),
+            Ok(Dispatch::World(cmd)) => {
+               Arbiter::spawn(async move {
+                  match manager.send(RouteCommand{world, cmd}).await {
+                     Ok(Ok(text)) => println!("{}", text),
+                     Ok(Err(e)) => println!("error: {}", e),
+                     Err(e) => println!("error: world unavailable {:?}", e),
+                  }
+               });
+            },
+            Ok(Dispatch::Manager(cmd)) => {
+               Arbiter::spawn(async move {
+                  match manager.send(cmd).await {
+                     Ok(Ok(text)) => println!("{}", text),
+                     Ok(Err(e)) => println!("error: {}", e),
+                     Err(e) => println!("error: world manager unavailable {:?}", e),
+                  }
+               });
+            },
+            Ok(Dispatch::Sync(cmd)) => {
+               let request = match SyncRequest::from_command(&cmd, None) {
+                  Ok(request) => request,
+                  Err(e) => {
+                     println!("error: {}", e);
+                     return;
+                  }
+               };
+               let sync = SyncCommand{client_id: CONSOLE_SYNC_CLIENT, request};
+               Arbiter::spawn(async move {
+                  match manager.send(RouteSync{world, sync}).await {
+                     Ok(Ok(text)) => println!("{}", text),
+                     Ok(Err(e)) => println!("error: {}", e),
+                     Err(e) => println!("error: world unavailable {:?}", e),
+                  }
+               });
+            },
+            Err(e) => println!("error: {}", e),
+         }
     }
 }
 
 impl Handler<WorldPong> for IOWorldActior {
     type Result = ();
 
-    fn handle(&mut self, _: WorldPong, ctx: &mut Context<Self>) {
+    fn handle(&mut self, _: WorldPong, _ctx: &mut Context<Self>) {
             println!("WorldPong");
     }
 }
\ No newline at end of file
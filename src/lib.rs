@@ -0,0 +1,13 @@
+//! Lunacia world simulation without the server around it.
+//!
+//! Build a world with `Simulation::new(&config)`, queue player inputs with
+//! `submit`, advance it with `step` and collect what happened with `drain_events`.
+
+pub mod config;
+pub mod ecs;
+pub mod scenario;
+pub mod simulation;
+
+pub use crate::config::{ServerConfig};
+pub use crate::ecs::types::{LunaciaWorldEvent, PlayerInputRequest};
+pub use crate::simulation::{Simulation};
@@ -16,11 +16,40 @@ use std::collections::VecDeque;
 use legion::prelude::*;
 
 use super::*;
-use crate::ecs::components::{*};
-
-use crate::ecs::submap::{TileMap};
-use crate::ecs::types::{*};
-use crate::ecs::systems;
+use lunacia::ecs::components::{*};
+
+use lunacia::ecs::types::{*};
+use lunacia::ecs::sync::{ClientSyncResource, SyncMessage};
+use lunacia::ecs::admin;
+use lunacia::ecs::map_edit::{self, TileKind};
+use lunacia::ecs::building::{BuildingKind};
+use lunacia::ecs::render::{self, RenderOptions};
+use lunacia::simulation::{Simulation};
+use lunacia::config::{ServerConfig, MAX_CATCH_UP_STEPS, MAX_FIXED_TIME_MS};
+use crate::commands::{AdminCommand, CommandError};
+
+/// Pixels per tile of `render` images
+const PPM_SCALE: usize = 4;
+
+/// Fastest simulated time per real time, catching up runs that many more frames per tick
+pub const MAX_TIME_SCALE: f32 = 16.0;
+
+/// Frames one `step` command runs, they run inside the actor before it answers
+pub const MAX_STEP_FRAMES: u32 = 1000;
+
+/// Frames asked by a `step` command, within `MAX_STEP_FRAMES`
+pub fn step_frames_arg(cmd: &AdminCommand) -> Result<u32, CommandError> {
+    let frames = cmd.args.opt_uint("frames").unwrap_or(1);
+    if frames > MAX_STEP_FRAMES {
+        return Err(CommandError::InvalidArg {
+            command: cmd.name.to_string(),
+            arg: "frames".to_string(),
+            value: frames.to_string(),
+            expected: format!("at most {} frames", MAX_STEP_FRAMES),
+        });
+    }
+    Ok(frames)
+}
 
 #[derive(Message)]
 #[rtype(result = "()")]
@@ -32,426 +61,648 @@ pub struct PingWorld {
 #[rtype(result = "()")]
 pub struct WorldPong;
 
+/// Builds the world from `config` and starts updating it, the world stops
+/// itself when loading fails
+#[derive(Message)]
+#[rtype(result = "Result<(), String>")]
+pub struct StartWorld {
+    pub config: ServerConfig,
+}
+
+/// Stops updating and drops the world
 #[derive(Message)]
 #[rtype(result = "()")]
-pub struct StartWorld;
+pub struct StopWorld;
 
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct UpdateWorld;
 
+pub enum WorldQuery {
+    Stats,
+    Entity(u32),
+    /// chunk containing the land position
+    Chunk(i32, i32),
+    PathStats,
+    Tile(i32, i32),
+    /// land parcel containing the tile
+    Parcel(i32, i32),
+    OwnerParcels(u32),
+    Building(u32),
+    /// buildings and stock of an owner
+    OwnerBuildings(u32),
+}
+
+/// Read-only look into the world, answered as JSON
+pub struct InspectWorld(pub WorldQuery);
+
+impl Message for InspectWorld {
+    type Result = Result<serde_json::Value, CommandError>;
+}
+
+/// Metrics as Prometheus text, or a console summary when `prometheus` is false
+#[derive(Message)]
+#[rtype(result = "String")]
+pub struct RenderMetrics {
+    pub prometheus: bool,
+}
+
+/// Snapshot or delta of a subscribed chunk for one client
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct WorldSync {
+    pub client_id: u32,
+    pub msg: SyncMessage,
+}
+
+/// What a sync client asks of a world
+pub enum SyncRequest {
+    /// Watch the chunks within `range` of (tx, ty), messages go to `recipient`
+    /// or to the console when there is none
+    Subscribe {
+        tx: i32,
+        ty: i32,
+        range: u32,
+        recipient: Option<Recipient<WorldSync>>,
+    },
+    Unsubscribe,
+    Ack(u32),
+    Resync,
+}
+
+impl SyncRequest {
+    /// Reads a `Dispatch::Sync` command, the client id is not part of it
+    pub fn from_command(cmd: &AdminCommand, recipient: Option<Recipient<WorldSync>>) -> Result<Self, CommandError> {
+        let args = &cmd.args;
+        match cmd.name {
+            "sub" => Ok(SyncRequest::Subscribe {
+                tx: args.int("x")?,
+                ty: args.int("y")?,
+                range: args.opt_uint("range").unwrap_or(1),
+                recipient: recipient,
+            }),
+            "unsub" => Ok(SyncRequest::Unsubscribe),
+            "ack" => Ok(SyncRequest::Ack(args.uint("seq")?)),
+            "resync" => Ok(SyncRequest::Resync),
+            _ => Err(CommandError::Unknown(cmd.name.to_string())),
+        }
+    }
+}
+
+/// A sync request of the client bound to the session sending it
+pub struct SyncCommand {
+    pub client_id: u32,
+    pub request: SyncRequest,
+}
+
+impl Message for SyncCommand {
+    type Result = String;
+}
+
+/// Prints the events of the chunks around the console focus
+fn print_focused_events(focus: &LandPos, evts: &[(i32, LunaciaWorldEvent)]) {
+    let visible_chunk_keys = focus.get_hash_map_key_successors(1);
+    for (chunk_key, evt) in evts.iter() {
+        if visible_chunk_keys.contains(&chunk_key){
+            match evt {
+                LunaciaWorldEvent::EventSpawn{frame, id, model, tx, ty} => {
+                    println!("EventSpawn: {:?} {:?} {:?} {:?},{:?}", frame, id, model, tx, ty);
+                },
+                LunaciaWorldEvent::EventRelocation{frame, id, tx, ty} => {
+                    println!("EventRelocation: {:?} {:?} {:?},{:?}", frame, id, tx, ty);
+                },
+                LunaciaWorldEvent::EventMoveSegment{frame, id, sx, sy, tx, ty, vx, vy, arrive_frame} => {
+                    println!("EventMoveSegment: {:?} {:?} {:?},{:?} -> {:?},{:?} vel {:?},{:?} arrive {:?}", frame, id, sx, sy, tx, ty, vx, vy, arrive_frame);
+                },
+                LunaciaWorldEvent::EventTileChanged{frame, tx, ty, tile} => {
+                    println!("EventTileChanged: {:?} {:?},{:?} = {:?}", frame, tx, ty, tile);
+                },
+                LunaciaWorldEvent::EventPathFailed{frame, id, sx, sy, tx, ty, fallback} => {
+                    println!("EventPathFailed: {:?} {:?} {:?},{:?} -> {:?},{:?} fallback {:?}", frame, id, sx, sy, tx, ty, fallback);
+                },
+                LunaciaWorldEvent::EventBuildingPlaced{frame, id, owner, model, tx, ty, w, h, done_frame} => {
+                    println!("EventBuildingPlaced: {:?} {:?} owner {:?} model {:?} {:?},{:?} {:?}x{:?} done {:?}", frame, id, owner, model, tx, ty, w, h, done_frame);
+                },
+                LunaciaWorldEvent::EventBuildingCompleted{frame, id, owner, model, tx, ty} => {
+                    println!("EventBuildingCompleted: {:?} {:?} owner {:?} model {:?} {:?},{:?}", frame, id, owner, model, tx, ty);
+                },
+            }
+        }
+    }
+}
+
+/// Commands about the entities and tiles of the simulation
+const SIMULATION_COMMANDS: [&str; 12] = ["spawn", "despawn", "teleport", "tile", "render", "inspect", "chunk", "parcel", "parcels", "build", "building", "buildings"];
+
+fn simulation_command(sim: &mut Simulation, cmd: &AdminCommand) -> Result<String, CommandError> {
+    let args = &cmd.args;
+    match cmd.name {
+        "spawn" => {
+            let model_name = args.word("model")?;
+            let model = admin::model_from_name(model_name).ok_or_else(|| CommandError::InvalidArg {
+                command: cmd.name.to_string(),
+                arg: "model".to_string(),
+                value: model_name.to_string(),
+                expected: "axie, chimera or resource".to_string(),
+            })?;
+            let (x, y) = (args.int("x")?, args.int("y")?);
+            let owner = args.opt_uint("owner").unwrap_or(1);
+            let (world, resources) = (&mut sim.world, &mut sim.resources);
+            let id = admin::spawn(world, resources, model, x, y, owner).map_err(CommandError::Failed)?;
+            Ok(format!("Spawned {} {} at {},{}", model_name, id, x, y))
+        },
+        "despawn" => {
+            let id = args.uint("id")?;
+            let (world, resources) = (&mut sim.world, &mut sim.resources);
+            admin::despawn(world, resources, id).map_err(CommandError::NotFound)?;
+            Ok(format!("Despawned {}", id))
+        },
+        "teleport" => {
+            let (id, x, y) = (args.uint("id")?, args.int("x")?, args.int("y")?);
+            let (world, resources) = (&mut sim.world, &mut sim.resources);
+            admin::teleport(world, resources, id, x, y).map_err(CommandError::Failed)?;
+            Ok(format!("Teleported {} to {},{}", id, x, y))
+        },
+        "tile" => {
+            let kind_name = args.word("kind")?;
+            let kind = TileKind::from_name(kind_name).ok_or_else(|| CommandError::InvalidArg {
+                command: cmd.name.to_string(),
+                arg: "kind".to_string(),
+                value: kind_name.to_string(),
+                expected: "road, alley, land, resource or clear".to_string(),
+            })?;
+            let (x, y) = (args.int("x")?, args.int("y")?);
+            let (world, resources) = (&mut sim.world, &mut sim.resources);
+            let report = map_edit::set_tile(world, resources, x, y, kind).map_err(CommandError::Failed)?;
+            let mut text = format!("Tile {},{}: {} -> {}, {} paths dropped, {} units replanning, {} flow fields rebuilt, parcels {:?} removed",
+                x, y, report.old_tile, report.tile, report.invalidated_paths, report.repathed_units, report.rebuilt_flow_fields, report.removed_parcels);
+            if report.removed_buildings.len() > 0 {
+                text.push_str(&format!(", buildings {:?} torn down", report.removed_buildings));
+            }
+            Ok(text)
+        },
+        "render" => {
+            let w = args.opt_uint("w").unwrap_or(40) as i32;
+            let h = args.opt_uint("h").unwrap_or(w as u32) as i32;
+            let opts = RenderOptions::new(args.int("x")?, args.int("y")?, w, h);
+            let (world, resources) = (&mut sim.world, &mut sim.resources);
+            match args.opt_word("file") {
+                Some(name) => {
+                    let path = render::write_ppm(world, resources, &opts, PPM_SCALE, name).map_err(CommandError::Failed)?;
+                    Ok(format!("Rendered {},{} {}x{} to {}", opts.x, opts.y, w, h, path))
+                },
+                None => render::render_ascii(world, resources, &opts).map_err(CommandError::Failed),
+            }
+        },
+        "inspect" => {
+            let id = args.uint("id")?;
+            let world = &mut sim.world;
+            let info = admin::inspect_entity(world, id).ok_or_else(|| CommandError::NotFound(format!("entity {}", id)))?;
+            Ok(format!("{:#?}", info))
+        },
+        "chunk" => {
+            let chunk_key = LandPos(args.int("x")?, args.int("y")?).get_hash_map_key();
+            let resources = &sim.resources;
+            Ok(format!("{:#?}", admin::list_chunk(resources, chunk_key)))
+        },
+        "parcel" => {
+            let (x, y) = (args.int("x")?, args.int("y")?);
+            let parcel = admin::parcel_info(&sim.resources, x, y)
+                .ok_or_else(|| CommandError::NotFound(format!("parcel at {},{}", x, y)))?;
+            Ok(format!("{:#?}", parcel))
+        },
+        "parcels" => {
+            let owner = args.uint("owner")?;
+            Ok(format!("{:#?}", admin::owner_parcels(&sim.resources, owner)))
+        },
+        "build" => {
+            let kind_name = args.word("kind")?;
+            let kind = BuildingKind::from_name(kind_name).ok_or_else(|| CommandError::InvalidArg {
+                command: cmd.name.to_string(),
+                arg: "kind".to_string(),
+                value: kind_name.to_string(),
+                expected: "storage or house".to_string(),
+            })?;
+            let (x, y) = (args.int("x")?, args.int("y")?);
+            let owner = args.opt_uint("owner").unwrap_or(1);
+            let (world, resources) = (&mut sim.world, &mut sim.resources);
+            let id = admin::place_building(world, resources, kind, owner, x, y).map_err(CommandError::Failed)?;
+            Ok(format!("Building {} {} started at {},{} for owner {}", kind_name, id, x, y, owner))
+        },
+        "building" => {
+            let id = args.uint("id")?;
+            let building = admin::building_info(&sim.resources, id).ok_or_else(|| CommandError::NotFound(format!("building {}", id)))?;
+            Ok(format!("{:#?}", building))
+        },
+        "buildings" => {
+            let owner = args.uint("owner")?;
+            Ok(format!("{:#?}", admin::owner_buildings(&sim.resources, owner)))
+        },
+        _ => Err(CommandError::Unknown(cmd.name.to_string()))
+    }
+}
+
+/// Answers a read-only query about a simulation
+fn inspect_simulation(sim: &mut Simulation, query: WorldQuery) -> Result<serde_json::Value, CommandError> {
+    let (world, resources) = (&mut sim.world, &mut sim.resources);
+    let value = match query {
+        WorldQuery::Stats => serde_json::to_value(admin::world_stats(world, resources)),
+        WorldQuery::Entity(id) => {
+            let info = admin::inspect_entity(world, id).ok_or_else(|| CommandError::NotFound(format!("entity {}", id)))?;
+            serde_json::to_value(info)
+        },
+        WorldQuery::Chunk(x, y) => serde_json::to_value(admin::list_chunk(resources, LandPos(x, y).get_hash_map_key())),
+        WorldQuery::PathStats => serde_json::to_value(admin::path_cache_stats(resources)),
+        WorldQuery::Tile(x, y) => {
+            let info = admin::tile_info(resources, x, y).ok_or_else(|| CommandError::NotFound(format!("tile {},{}", x, y)))?;
+            serde_json::to_value(info)
+        },
+        WorldQuery::Parcel(x, y) => {
+            let parcel = admin::parcel_info(resources, x, y).ok_or_else(|| CommandError::NotFound(format!("parcel at {},{}", x, y)))?;
+            serde_json::to_value(parcel)
+        },
+        WorldQuery::OwnerParcels(owner) => serde_json::to_value(admin::owner_parcels(resources, owner)),
+        WorldQuery::Building(id) => {
+            let building = admin::building_info(resources, id).ok_or_else(|| CommandError::NotFound(format!("building {}", id)))?;
+            serde_json::to_value(building)
+        },
+        WorldQuery::OwnerBuildings(owner) => serde_json::to_value(admin::owner_buildings(resources, owner)),
+    };
+    value.map_err(|e| CommandError::Failed(e.to_string()))
+}
+
 #[derive(Default)]
 pub struct LunaciaWorldActor {
+    name: String,
     up_time: u128,
     running_time_ms: u128,
     accumulated_time: u128,
+    dropped_time_ms: u128,
     fixed_time_step: u64,
-    universe: Option::<Universe>,
-    world: Option::<World>,
-    schedule: Option<Schedule>,
-    resources: Option<Resources>,
-    inputs: Vec<PlayerInputRequest>,
+    max_catch_up_steps: u32,
+    time_scale: f32,
+    paused: bool,
+    simulation: Option<Simulation>,
     outputs: Vec<WorldPong>,
+    sync_recipients: HashMap<u32, Recipient<WorldSync>>,
     inputing: bool
 }
 
 impl Actor for LunaciaWorldActor {
     type Context = Context<Self>;
-}
-impl actix::Supervised for LunaciaWorldActor {}
 
-impl ArbiterService for LunaciaWorldActor {
-   fn service_started(&mut self, ctx: &mut Context<Self>) {
-        match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
-            Ok(n) => {
-                self.up_time = n.as_millis();
-            },
-            Err(_) => {
-                panic!("SystemTime before UNIX EPOCH!")
-            }
-        }
-        self.fixed_time_step = 200; 
-        self.inputing = true;
-
-        let mut resources = Resources::default();
-        let mut tile_map = TileMap::new(390, 390);
-
-        let mut init_axies = Vec::<(LandPos, HomeLand)>::new();
-        let mut init_resource_nodes = Vec::<(LandPos,)>::new();
-        match tile_map.load_map() {
-            Ok(v) => {
-               
-                // for y in 30..100 {
-                //     for x in 30..100 {
-                //         if tile_map.is_land_tile(&(x, y)) {
-                //             let land_pos = LandPos(x, y);
-                //             if init_axies.len() == 0 {
-                //                 init_axies.push((land_pos, HomeLand(land_pos)));
-                //             }
-                //         } else if tile_map.is_resource_tile(&(x, y)) {
-                //             //println!("resource node {:?} {:?}", x, y);
-                //             if init_resource_nodes.len() ==0 {
-                //                 init_resource_nodes.push((LandPos(x, y),));
-                                
-                //         }
-                //     }
-                // }
-                for y in 30..100 {
-                    for x in 30..100 {
-                        if tile_map.is_land_tile(&(x, y)) {
-                            let land_pos = LandPos(x, y);
-                            init_axies.push((land_pos, HomeLand(land_pos)));
-                        } else if tile_map.is_resource_tile(&(x, y)) {
-                            init_resource_nodes.push((LandPos(x, y),));
-                        }
-                    }
-                }
+    fn started(&mut self, _: &mut Context<Self>) {
+        println!("World {} started", self.name);
+    }
 
-                
-                resources.insert(TileMapResource(tile_map));
-            },
-            Err(e) => {
-                println!("error parsing header: {:?}", e);
-                panic!();
-            }
-        }
+    fn stopped(&mut self, _: &mut Context<Self>) {
+        println!("World {} stopped", self.name);
+    }
+}
 
-        resources.insert(GameConfigResource{
-            fixed_time_ms: self.fixed_time_step, 
-            number_of_updates: 0,
-            map_width: 390, 
-            map_height: 390,
-            tmp_focusing_pos: (0, 0)
-        });
-        resources.insert(EmitEventResource(Vec::<(i32, LunaciaWorldEvent)>::new()));
-        resources.insert(QuadrantDataHashMapResource(HashMap::new()));
-        resources.insert(PathwayHashMapResource(HashMap::new()));
-        self.resources = Some(resources);
-
-        let universe = Universe::new();
-        let mut world = universe.create_world();
-
-        //Init static building
-        if init_resource_nodes.len() > 0 {
-            println!("Total ressource nodes: {:?}", init_resource_nodes.len());
-            world.insert(
-                (Model(BuildingModelType::ResourceNode as u32), Static,),
-                init_resource_nodes
-            );
+impl LunaciaWorldActor {
+    pub fn new(name: &str) -> Self {
+        LunaciaWorldActor {
+            name: name.to_string(),
+            time_scale: 1.0,
+            inputing: true,
+            ..Default::default()
         }
+    }
 
-        // world.insert(
-        //     (),
-        //     vec![
-        //         (LandPos(5, 5), ChimeraSpawner{ count: 1, cooldown_ms: 20001, tick_ms: 20000}),
-        //     ],
-        // );
-
-        // TODO load state
-        if init_axies.len() > 0 {
-            println!("Total axie: {:?}", init_axies.len());
-            world.insert(
-                (Owner(1), Model(UnitModelType::Axie as u32), AutoCollect),
-                init_axies
-            );
+    fn execute_frame(&mut self) {
+        if let Some(sim) = &mut self.simulation {
+            sim.step();
+            let focus = sim.resources.get::<GameConfigResource>().map_or((0, 0), |conf| conf.tmp_focusing_pos);
+            print_focused_events(&LandPos(focus.0, focus.1), &sim.drain_events());
         }
+    }
 
-        self.universe = Some(universe);
-        self.world = Some(world);
+    fn simulation_mut(&mut self) -> Result<&mut Simulation, CommandError> {
+        self.simulation.as_mut().ok_or_else(|| CommandError::Failed("world is not started".to_string()))
+    }
 
-        let update_chimera_spawners = systems::build_update_chimera_spawners();
-        let update_positions = systems::build_update_moving();
-        let update_follow_paths = systems::build_update_follow_paths();
-        let update_new_pos = systems::build_update_new_pos();
+    fn world_and_resources(&mut self) -> Result<(&mut World, &mut Resources), CommandError> {
+        let sim = self.simulation_mut()?;
+        Ok((&mut sim.world, &mut sim.resources))
+    }
 
-        // update positions using a system
-        let set_quadrant_data_hash_map = systems::build_set_quadrant_data_hash_map();
+    fn step_frames(&mut self, frames: u32) {
+        for _ in 0..frames {
+            self.execute_frame();
+        }
+        self.deliver_sync_messages();
+    }
 
-        let thread_local_example = Box::new(|world: &mut World, _resources: &mut Resources| {
-            let mut tmp_focusing_pos = LandPos(0, 0);
-            if let Some(conf) = &_resources.get::<GameConfigResource>() {
-                tmp_focusing_pos = LandPos(conf.tmp_focusing_pos.0, conf.tmp_focusing_pos.1);
-            }
-            // if let Some(p) = &mut _resources.get_mut::<PlayerInputResource>() {
-            //     let ins = &mut p.0;
-            //     //TODO quick verify valid input?
-            //     while let Some(inp) = &self.inputs.pop_front() {
-            //         //ins.push_back(*inp);
-            //     }
-            //     // let act = IOWorldActior::from_registry();
-            //     // act.do_send(WorldPong);
-            // }
-
-            
-
-            if let Some(p) = &mut _resources.get_mut::<EmitEventResource>() {
-                let evts = &mut p.0;
-                if evts.len() > 0 {
-                    let visible_chunk_keys = tmp_focusing_pos.get_hash_map_key_successors(1);
-                    for (chunk_key, evt) in evts.iter() {
-                        if visible_chunk_keys.contains(&chunk_key){
-                            match evt {
-                                LunaciaWorldEvent::EventSpawn{frame, id, model, tx, ty} => {
-                                    println!("EventSpawn: {:?} {:?} {:?} {:?},{:?}", frame, id, model, tx, ty);
-                                },
-                                LunaciaWorldEvent::EventRelocation{frame, id, tx, ty} => {
-                                    println!("EventRelocation: {:?} {:?} {:?},{:?}", frame, id, tx, ty);
-                                },
-                            }
-                        }
-                    }
-                    evts.clear();
+    fn set_config(&mut self, key: &str, value: f32) -> Result<(), CommandError> {
+        let invalid = |expected: &str| CommandError::InvalidArg {
+            command: "set".to_string(),
+            arg: key.to_string(),
+            value: value.to_string(),
+            expected: expected.to_string(),
+        };
+        if !value.is_finite() {
+            return Err(invalid("finite number"));
+        }
+        match key {
+            "fixed_time_ms" => {
+                if value < 1.0 || value > MAX_FIXED_TIME_MS as f32 {
+                    return Err(invalid(&format!("number of ms within 1..={}", MAX_FIXED_TIME_MS)));
                 }
-            };
-
-            if let Some(p) = &mut _resources.get_mut::<QuadrantDataHashMapResource>() {
-                let hm = &mut p.0;
-                {
-                    let query = <(Write<PlayerInput>, Read<PlayerInputGetStateAroundLand>)>::query();
-                    for (mut pi, lp) in query.iter_mut(world) {
-                        match &pi.status {
-                            0 => {
-                                tmp_focusing_pos = LandPos(lp.0, lp.1);
-                                let visible_chunk_keys = tmp_focusing_pos.get_hash_map_key_successors(1);
-                                let mut total = 0;
-                                for chunk_key in visible_chunk_keys.iter() {
-                                    //println!("chunk_key {:?}", chunk_key);
-                                    match hm.get(chunk_key) {
-                                        Some(chunk) => {
-                                            
-                                            for objs in chunk.values() {
-                                                for (entity, qd) in objs.iter() {
-                                                    total += 1;
-                                                    //println!("State: {:?} - {:?},{:?}", entity.index(), qd.land_pos.0, qd.land_pos.1);
-                                                }
-                                            }
-                                        },
-                                        None => {}
-                                    }
-                                }
-                                println!("Focus at {:?},{:?} total entities: {:?}", lp.0, lp.1, total);
-                                pi.status += 1;
-                            },
-                            _ => ()
-                        }
+                self.fixed_time_step = value as u64;
+                if let Some(sim) = &mut self.simulation {
+                    if let Some(mut conf) = sim.resources.get_mut::<GameConfigResource>() {
+                        conf.fixed_time_ms = self.fixed_time_step;
                     }
                 }
+            },
+            "max_catch_up_steps" => {
+                if value < 1.0 || value > MAX_CATCH_UP_STEPS as f32 {
+                    return Err(invalid(&format!("number of frames within 1..={}", MAX_CATCH_UP_STEPS)));
+                }
+                self.max_catch_up_steps = value as u32;
+            },
+            "time_scale" => {
+                if value <= 0.0 || value > MAX_TIME_SCALE {
+                    return Err(invalid(&format!("scale > 0 and <= {}", MAX_TIME_SCALE)));
+                }
+                self.time_scale = value;
+            },
+            _ => return Err(invalid("known setting"))
+        }
+        Ok(())
+    }
 
-                {
-                    let mut input_hm : HashMap <(u32, u32), PlayerInputAxieGatherResource> = HashMap::new();
-
-                    let query = <(Write<PlayerInput>, Read<PlayerInputAxie>, Read<PlayerInputAxieGatherResource>)>::query();
-                    for (mut pi, ax, gr) in query.iter_mut(world) {
-                        match &pi.status {
-                            0 => {
-                                pi.status += 1;
-                                input_hm.insert((pi.owner, ax.axie_index), *gr);
-                            },
-                            _ => ()
-                        }
-                    }
-
-                    for (key, val) in input_hm.iter() {
-                        let owner = Owner(key.0);
-                        let axie_query = <(Read<LandPos>)>::query()
-                            .filter(tag_value(&owner) & tag_value(&Model(UnitModelType::Axie as u32)));
-                        let mut axie_found : Option<Entity> = None;
-                        for (mut axie_entity, (axie_pos)) in axie_query.iter_entities_mut(world) {
-                            if axie_entity.index() == key.1 {
-                                axie_found = Some(axie_entity);
-                            }
-                        }
-                        match axie_found {
-                            Some(entity) => {
-                                // if world.get_component::<GGoal>(entity) == None {
-                                //     world.add_component(entity, GatherResourceGoal{
-                                //         step:0, 
-                                //         home_pos:LandPos(5, 5), 
-                                //         target_pos:LandPos(21, 21)}
-                                //     );
-                                // } else{
-                                //     println!("Axie busing");
-                                // }
-                                
-                            },
-                            None => {
-                                println!("Invalid axie input");
-                            }
-                        }
-                        //println!("key: {} val: {}", key, val);
-                    }
+    fn render_metrics(&self, prometheus: bool) -> String {
+        let metrics = match &self.simulation {
+            Some(sim) => sim.metrics(),
+            None => None,
+        };
+        match metrics {
+            Some(m) => {
+                let mut text = if prometheus { m.render_prometheus() } else { m.render_summary() };
+                if prometheus {
+                    text += &format!("# TYPE lunacia_dropped_time_ms_total counter\nlunacia_dropped_time_ms_total {}\n", self.dropped_time_ms);
+                } else {
+                    text += &format!("\ndropped: {} ms", self.dropped_time_ms);
                 }
-                
-                //hm.clear();
-            }
-            if let Some(conf) = &mut _resources.get_mut::<GameConfigResource>() {
-                conf.number_of_updates += 1;
-                conf.tmp_focusing_pos = (tmp_focusing_pos.0, tmp_focusing_pos.1);
-            }
-        });
+                text
+            },
+            None => String::new(),
+        }
+    }
 
-        let mut schedule = Schedule::builder()
-            .add_system(set_quadrant_data_hash_map)
-            //.add_system(update_chimeras_as_boid)
-            .add_system(update_follow_paths)
-            .add_system(update_positions)
-            .add_system(update_chimera_spawners)
-            .add_system(update_new_pos)
+    fn clock_status(&self) -> String {
+        let frame = match &self.simulation {
+            Some(sim) => sim.frame(),
+            None => 0,
+        };
+        format!("World {} clock frame {:?} paused {:?} scale {:?} dropped {:?} ms", self.name, frame, self.paused, self.time_scale, self.dropped_time_ms)
+    }
 
-            .add_system(systems::build_gather_resource_goals())
-            .add_system(systems::build_gather_resource_actions())
-            .add_system(systems::build_release_resource_actions())
+    fn with_client_sync<F>(&mut self, f: F) where F: FnOnce(&mut ClientSyncResource) {
+        if let Some(sim) = &mut self.simulation {
+            if let Some(mut sync) = sim.resources.get_mut::<ClientSyncResource>() {
+                f(&mut sync);
+            }
+        }
+    }
 
-            //.add_system(systems::build_player_input_axie_gather_resource())
+    fn sync_subscribe(&mut self, client_id: u32, tx: i32, ty: i32, range: u32, recipient: Option<Recipient<WorldSync>>) {
+        let chunk_keys = LandPos(tx, ty).get_hash_map_key_successors(range);
+        self.with_client_sync(|sync| sync.subscribe(client_id, &chunk_keys));
+        match recipient {
+            Some(r) => {
+                self.sync_recipients.insert(client_id, r);
+            },
+            None => {
+                self.sync_recipients.remove(&client_id);
+            }
+        }
+    }
 
-            .add_system(systems::build_player_input_cleans())
+    fn sync_unsubscribe(&mut self, client_id: u32) {
+        self.with_client_sync(|sync| sync.unsubscribe(client_id));
+        self.sync_recipients.remove(&client_id);
+    }
 
-            .add_system(systems::build_auto_collect_resources())
-            
-            // This flushes all command buffers of all systems.
-            .flush()
-            // a thread local system or function will wait for all previous systems to finish running,
-            // and then take exclusive access of the world.
-            .add_thread_local_fn(thread_local_example)
-            .build();
+    fn deliver_sync_messages(&mut self) {
+        let mut messages = Vec::new();
+        self.with_client_sync(|sync| messages = sync.drain_outbox());
+        let mut gone_clients = Vec::new();
+        for (client_id, msg) in messages {
+            match self.sync_recipients.get(&client_id) {
+                Some(recipient) => {
+                    if recipient.do_send(WorldSync{client_id: client_id, msg: msg}).is_err() {
+                        gone_clients.push(client_id);
+                    }
+                },
+                None => {
+                    println!("Sync -> {:?}: {:?}", client_id, msg);
+                }
+            }
+        }
+        for client_id in gone_clients {
+            println!("Sync client {:?} gone", client_id);
+            self.sync_unsubscribe(client_id);
+        }
+    }
+}
 
-        self.schedule = Some(schedule);
+impl Handler<SyncCommand> for LunaciaWorldActor {
+    type Result = String;
 
-        println!("LunaciaWorldActor Service started");
-   }
+    fn handle(&mut self, msg: SyncCommand, _: &mut Context<Self>) -> Self::Result {
+        let client_id = msg.client_id;
+        match msg.request {
+            SyncRequest::Subscribe{tx, ty, range, recipient} => {
+                self.sync_subscribe(client_id, tx, ty, range, recipient);
+                format!("Sync client {} subscribed", client_id)
+            },
+            SyncRequest::Unsubscribe => {
+                self.sync_unsubscribe(client_id);
+                format!("Sync client {} unsubscribed", client_id)
+            },
+            SyncRequest::Ack(seq) => {
+                self.with_client_sync(|sync| sync.ack(client_id, seq));
+                format!("Sync client {} acked {}", client_id, seq)
+            },
+            SyncRequest::Resync => {
+                self.with_client_sync(|sync| sync.resync(client_id));
+                format!("Sync client {} resyncing", client_id)
+            },
+        }
+    }
 }
 
 impl Handler<StartWorld> for LunaciaWorldActor {
-   type Result = ();
+   type Result = Result<(), String>;
 
-   fn handle(&mut self, _: StartWorld, ctx: &mut Context<Self>) {
-        println!("StartWorld");
+   fn handle(&mut self, msg: StartWorld, ctx: &mut Context<Self>) -> Self::Result {
+        println!("StartWorld {}", self.name);
+        if self.simulation.is_some() {
+            return Err(format!("world {} already started", self.name));
+        }
+
+        match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+            Ok(n) => {
+                self.up_time = n.as_millis();
+            },
+            Err(_) => {
+                panic!("SystemTime before UNIX EPOCH!")
+            }
+        }
+        self.fixed_time_step = msg.config.fixed_time_ms;
+        self.max_catch_up_steps = msg.config.max_catch_up_steps;
+
+        match Simulation::new(&msg.config) {
+            Ok(sim) => {
+                self.simulation = Some(sim);
+            },
+            Err(e) => {
+                let err = format!("error loading world {}: {:?}", self.name, e);
+                println!("{}", err);
+                ctx.stop();
+                return Err(err);
+            }
+        }
 
         let addr = ctx.address();
 
         ctx.run_later(Duration::from_millis(1000), move |act, _| {
             addr.do_send(UpdateWorld);
         });
+        Ok(())
    }
 }
 
-impl Handler<PingWorld> for LunaciaWorldActor {
+impl Handler<StopWorld> for LunaciaWorldActor {
     type Result = ();
- 
-    fn handle(&mut self, msg: PingWorld, ctx: &mut Context<Self>) {
-        if msg.data.len() > 1 {
-            let mut iter = msg.data.split_ascii_whitespace();
-            match iter.next() {
-                Some("i") => {
-                    let mut tx = 0;
-                    if let Some(v_str) = iter.next() {
-                        if let Ok(v) = v_str.parse::<i32>() {
-                            tx = v;
-                        }
-                    }
-                    let mut ty = 0;
-                    if let Some(v_str) = iter.next() {
-                        if let Ok(v) = v_str.parse::<i32>() {
-                            ty = v;
-                        }
-                    }
 
-                    self.inputs.push(PlayerInputRequest::GetPlayerState {
-                        request_id: 0,
-                        owner: 1,
-                        tx: tx,
-                        ty: ty
-                    });
-                },
-                Some("g") => {
-                    let mut e_index = 0;
-                    if let Some(v_str) = iter.next() {
-                        if let Ok(v) = v_str.parse::<u32>() {
-                            e_index = v;
-                        }
-                    }
-                    if e_index > 0 {
-                        println!("PlayerInputRequested {:?}", e_index);
-                        self.inputs.push(PlayerInputRequest::GatherResource {
-                            request_id: 0,
-                            owner: 1,
-                            axie_index: e_index
-                        });
-                    }
+    fn handle(&mut self, _: StopWorld, ctx: &mut Context<Self>) {
+        self.simulation = None;
+        ctx.stop();
+    }
+}
 
-                    self.inputing = false;
-                    
-                },
-                _ => {
-                    println!("skipped {:?}", msg.data);
-                }
-            }
+impl Handler<RenderMetrics> for LunaciaWorldActor {
+    type Result = String;
+
+    fn handle(&mut self, msg: RenderMetrics, _: &mut Context<Self>) -> Self::Result {
+        self.render_metrics(msg.prometheus)
+    }
+}
+
+impl Handler<InspectWorld> for LunaciaWorldActor {
+    type Result = Result<serde_json::Value, CommandError>;
+
+    fn handle(&mut self, msg: InspectWorld, _: &mut Context<Self>) -> Self::Result {
+        inspect_simulation(self.simulation_mut()?, msg.0)
+    }
+}
+
+impl Handler<AdminCommand> for LunaciaWorldActor {
+    type Result = Result<String, CommandError>;
+
+    fn handle(&mut self, cmd: AdminCommand, _: &mut Context<Self>) -> Self::Result {
+        if SIMULATION_COMMANDS.contains(&cmd.name) {
+            let result = simulation_command(self.simulation_mut()?, &cmd);
+            self.deliver_sync_messages();
+            return result;
+        }
+        let args = &cmd.args;
+        match cmd.name {
+            "focus" => {
+                let (tx, ty) = (args.int("x")?, args.int("y")?);
+                self.simulation_mut()?.submit(PlayerInputRequest::GetPlayerState {
+                    request_id: 0,
+                    owner: 1,
+                    tx: tx,
+                    ty: ty
+                });
+                Ok(format!("Focus requested at {},{}", tx, ty))
+            },
+            "gather" => {
+                let e_index = args.uint("axie")?;
+                self.simulation_mut()?.submit(PlayerInputRequest::GatherResource {
+                    request_id: 0,
+                    owner: 1,
+                    axie_index: e_index
+                });
+                self.inputing = false;
+                Ok(format!("PlayerInputRequested {}", e_index))
+            },
+            "set" => {
+                let key = args.word("key")?;
+                let value = args.float("value")?;
+                self.set_config(key, value)?;
+                Ok(format!("{} = {}", key, value))
+            },
+            "stats" => {
+                let clock = self.clock_status();
+                let (world, resources) = self.world_and_resources()?;
+                Ok(format!("{:#?}\n{}", admin::world_stats(world, resources), clock))
+            },
+            "pause" => {
+                self.paused = true;
+                self.accumulated_time = 0;
+                Ok(self.clock_status())
+            },
+            "resume" => {
+                self.paused = false;
+                Ok(self.clock_status())
+            },
+            "step" => {
+                self.step_frames(step_frames_arg(&cmd)?);
+                Ok(self.clock_status())
+            },
+            "speed" => {
+                self.set_config("time_scale", args.float("scale")?)?;
+                Ok(self.clock_status())
+            },
+            "clock" => Ok(self.clock_status()),
+            "metrics" => Ok(self.render_metrics(args.opt_word("format") == Some("prometheus"))),
+            _ => Err(CommandError::Unknown(cmd.name.to_string()))
         }
     }
- }
+}
 
 impl Handler<UpdateWorld> for LunaciaWorldActor {
     type Result = ();
 
     fn handle(&mut self, _: UpdateWorld, ctx: &mut Context<Self>) {
-        if let Some(resources) = &mut self.resources {
-            if let Some(schedule) = &mut self.schedule {
-                if let Some(world) = &mut self.world {
-
-                    if self.inputs.len() > 0 {
-                        let mut input_axies = Vec::new();
-                        let mut input_get_states = Vec::new();
-                        for input in &self.inputs {
-                            match input {
-                                PlayerInputRequest::GatherResource{request_id, owner, axie_index} => {
-                                    input_axies.push((PlayerInput{request_id: *request_id, owner: *owner, status: 0}, PlayerInputAxie{axie_index: *axie_index}, PlayerInputAxieGatherResource{resource_id: 1}))
-                                },
-                                PlayerInputRequest::GetPlayerState{request_id, owner, tx, ty} => {
-                                    input_get_states.push((PlayerInput{request_id: *request_id, owner: *owner, status: 0}, PlayerInputGetStateAroundLand(*tx, *ty)))
-                                },
-                                _ => {}
-                            }
-                        }
-                        self.inputs.clear();
-                        if input_axies.len() > 0 {
-                            world.insert(
-                                (),
-                                input_axies
-                            );
-                        }
-                        if input_get_states.len() > 0 {
-                            world.insert(
-                                (),
-                                input_get_states
-                            );
-                        }
-                    }
-                    
-                
-
-
-                    let mut now_ms : u128 = 0;
-                    match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
-                        Ok(n) => {
-                            now_ms = n.as_millis();
-                        },
-                        Err(_) => {
-                            panic!("SystemTime before UNIX EPOCH!")
-                        }
-                    }
-                    let dt_time = (now_ms - self.up_time) - self.running_time_ms;
-                    println!("dt_time {:?}", dt_time);
-
-                    self.running_time_ms += dt_time;
-                    self.accumulated_time += dt_time;
-
-                    while self.accumulated_time >= self.fixed_time_step as u128 {
-                        schedule.execute(world, resources);
-                        self.accumulated_time -= self.fixed_time_step as u128;
-                    }
-                };
-            };
-        };
+        let mut now_ms : u128 = 0;
+        match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+            Ok(n) => {
+                now_ms = n.as_millis();
+            },
+            Err(_) => {
+                panic!("SystemTime before UNIX EPOCH!")
+            }
+        }
+        let dt_time = (now_ms - self.up_time) - self.running_time_ms;
+        self.running_time_ms += dt_time;
+
+        if !self.paused {
+            self.accumulated_time += (dt_time as f64 * self.time_scale as f64) as u128;
+
+            // at a time scale above 1 more frames are due per wake up, so the cap grows with it
+            let max_steps = (self.max_catch_up_steps as f32 * self.time_scale.max(1.0)).ceil() as u32;
+            let fixed_time_step = self.fixed_time_step as u128;
+            let mut steps = 0;
+            while self.accumulated_time >= fixed_time_step && steps < max_steps {
+                self.execute_frame();
+                self.accumulated_time -= fixed_time_step;
+                steps += 1;
+            }
+            if self.accumulated_time >= fixed_time_step {
+                let dropped = self.accumulated_time - self.accumulated_time % fixed_time_step;
+                self.accumulated_time -= dropped;
+                self.dropped_time_ms += dropped;
+                println!("World {} is behind, dropped {:?} ms (total {:?} ms)", self.name, dropped, self.dropped_time_ms);
+            }
+        }
+        self.deliver_sync_messages();
  
         let addr = ctx.address();
 
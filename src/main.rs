@@ -3,6 +3,8 @@
 //! or [python console client](https://github.com/actix/examples/blob/master/websocket/websocket-client.py)
 //! could be used for testing.
 
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::time::{Duration, Instant, SystemTime};
 
 use actix::prelude::*;
@@ -12,33 +14,68 @@ use actix_web_actors::ws;
 use std::{io, thread};
 
 
-use crate::lunacia_world::{LunaciaWorldActor, PingWorld, WorldPong, StartWorld, UpdateWorld};
+use crate::lunacia_world::{LunaciaWorldActor, PingWorld, WorldPong, UpdateWorld, SyncCommand, SyncRequest, WorldSync};
 mod lunacia_world;
 
-use crate::io_world::{IOWorldActior};
+use crate::world_manager::{CreateWorld, GetWorld, RouteCommand, RouteSync, WorldManager, DEFAULT_WORLD};
+mod world_manager;
+
+use crate::io_world::{IOWorldActior, CONSOLE_SYNC_CLIENT};
 mod io_world;
 
-mod ecs;
+use crate::commands::{AdminCommand, CommandRegistry, Dispatch};
+mod commands;
+
+mod admin_api;
+
+mod bench;
+
+use lunacia::config::{ServerConfig};
+use lunacia::ecs::mapgen::{self, MapGenParams};
 
-/// How often heartbeat pings are sent
-const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
-/// How long before lack of client response causes a timeout
-const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Sync client id of the next WebSocket session
+static NEXT_SYNC_CLIENT: AtomicU32 = AtomicU32::new(CONSOLE_SYNC_CLIENT + 1);
 
-/// do websocket handshake and start `MyWebSocket` actor
-async fn ws_index(r: HttpRequest, stream: web::Payload) -> Result<HttpResponse, Error> {
-    println!("{:?}", r);
-    let res = ws::start(MyWebSocket::new(), &r, stream);
-    println!("{:?}", res);
+/// do websocket handshake and start `MyWebSocket` actor on the default world
+async fn ws_index(r: HttpRequest, stream: web::Payload, manager: web::Data<Addr<WorldManager>>, config: web::Data<ServerConfig>) -> Result<HttpResponse, Error> {
+    start_session(r, stream, manager.get_ref().clone(), &config, DEFAULT_WORLD.to_string()).await
+}
+
+/// same as `ws_index` for the world named in the path
+async fn ws_world_index(r: HttpRequest, stream: web::Payload, manager: web::Data<Addr<WorldManager>>, config: web::Data<ServerConfig>, path: web::Path<(String,)>) -> Result<HttpResponse, Error> {
+    start_session(r, stream, manager.get_ref().clone(), &config, path.0.clone()).await
+}
+
+async fn start_session(r: HttpRequest, stream: web::Payload, manager: Addr<WorldManager>, config: &ServerConfig, world: String) -> Result<HttpResponse, Error> {
+    tracing::debug!("{:?}", r);
+    match manager.send(GetWorld{name: Some(world.clone())}).await {
+        Ok(Some(_)) => {},
+        _ => return Ok(HttpResponse::NotFound().body(format!("no world {}", world))),
+    }
+    let admin = admin_api::authorized(&r);
+    let res = ws::start(MyWebSocket::new(manager, world, config, admin), &r, stream);
+    tracing::debug!("{:?}", res);
     res
 }
 
 /// websocket connection is long running connection, it easier
 /// to handle with an actor
 struct MyWebSocket {
-    /// Client must send ping at least once per 10 seconds (CLIENT_TIMEOUT),
+    /// Client must send ping at least once per `client_timeout`,
     /// otherwise we drop connection.
     hb: Instant,
+    hb_interval: Duration,
+    client_timeout: Duration,
+    /// Text messages are admin commands run against this world,
+    /// or the one of their `@world` prefix
+    manager: Addr<WorldManager>,
+    world: String,
+    registry: CommandRegistry,
+    /// The handshake carried the admin token, admin commands are refused otherwise
+    admin: bool,
+    /// Sync client of this session, and the worlds it subscribed to
+    sync_client: u32,
+    sync_worlds: HashSet<String>,
 }
 
 impl Actor for MyWebSocket {
@@ -48,6 +85,27 @@ impl Actor for MyWebSocket {
     fn started(&mut self, ctx: &mut Self::Context) {
         self.hb(ctx);
     }
+
+    fn stopped(&mut self, _: &mut Self::Context) {
+        for world in self.sync_worlds.drain() {
+            self.manager.do_send(RouteSync{
+                world: Some(world),
+                sync: SyncCommand{client_id: self.sync_client, request: SyncRequest::Unsubscribe},
+            });
+        }
+    }
+}
+
+/// Sync messages of the subscribed chunks, sent to the client as JSON
+impl Handler<WorldSync> for MyWebSocket {
+    type Result = ();
+
+    fn handle(&mut self, msg: WorldSync, ctx: &mut Self::Context) {
+        match serde_json::to_string(&msg.msg) {
+            Ok(json) => ctx.text(json),
+            Err(e) => println!("Sync client {:?} message not sent: {:?}", msg.client_id, e),
+        }
+    }
 }
 
 /// Handler for `ws::Message`
@@ -58,7 +116,7 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for MyWebSocket {
         ctx: &mut Self::Context,
     ) {
         // process websocket messages
-        println!("WS: {:?}", msg);
+        tracing::debug!("WS: {:?}", msg);
         match msg {
             Ok(ws::Message::Ping(msg)) => {
                 self.hb = Instant::now();
@@ -67,7 +125,7 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for MyWebSocket {
             Ok(ws::Message::Pong(_)) => {
                 self.hb = Instant::now();
             }
-            Ok(ws::Message::Text(text)) => ctx.text(text),
+            Ok(ws::Message::Text(text)) => self.run_command(&text, ctx),
             Ok(ws::Message::Binary(bin)) => ctx.binary(bin),
             Ok(ws::Message::Close(_)) => {
                 ctx.stop();
@@ -78,17 +136,84 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for MyWebSocket {
 }
 
 impl MyWebSocket {
-    fn new() -> Self {
-        Self { hb: Instant::now() }
+    fn new(manager: Addr<WorldManager>, world: String, config: &ServerConfig, admin: bool) -> Self {
+        Self {
+            hb: Instant::now(),
+            hb_interval: config.heartbeat_interval(),
+            client_timeout: config.client_timeout(),
+            manager,
+            world,
+            registry: CommandRegistry::with_builtins(),
+            admin,
+            sync_client: NEXT_SYNC_CLIENT.fetch_add(1, Ordering::Relaxed),
+            sync_worlds: HashSet::new(),
+        }
+    }
+
+    fn run_command(&mut self, text: &str, ctx: &mut <Self as Actor>::Context) {
+        let (world, line) = world_manager::split_world_prefix(text);
+        let world = world.unwrap_or_else(|| self.world.clone());
+        match self.registry.dispatch_as(line, self.admin) {
+            Ok(Dispatch::Reply(reply)) => ctx.text(reply),
+            Ok(Dispatch::World(cmd)) => {
+                ctx.spawn(self.manager.send(RouteCommand{world: Some(world), cmd}).into_actor(self).map(|res, _, ctx| {
+                    match res {
+                        Ok(Ok(reply)) => ctx.text(reply),
+                        Ok(Err(e)) => ctx.text(format!("error: {}", e)),
+                        Err(_) => ctx.text("error: world unavailable"),
+                    }
+                }));
+            },
+            Ok(Dispatch::Manager(cmd)) => {
+                ctx.spawn(self.manager.send(cmd).into_actor(self).map(|res, _, ctx| {
+                    match res {
+                        Ok(Ok(reply)) => ctx.text(reply),
+                        Ok(Err(e)) => ctx.text(format!("error: {}", e)),
+                        Err(_) => ctx.text("error: world manager unavailable"),
+                    }
+                }));
+            },
+            Ok(Dispatch::Sync(cmd)) => self.sync_command(world, cmd, ctx),
+            Err(e) => ctx.text(format!("error: {}", e)),
+        }
+    }
+
+    /// Runs a sync command for the sync client of this session, its
+    /// messages come back as `WorldSync`
+    fn sync_command(&mut self, world: String, cmd: AdminCommand, ctx: &mut <Self as Actor>::Context) {
+        let request = match SyncRequest::from_command(&cmd, Some(ctx.address().recipient())) {
+            Ok(request) => request,
+            Err(e) => {
+                ctx.text(format!("error: {}", e));
+                return;
+            }
+        };
+        match request {
+            SyncRequest::Subscribe{..} => {
+                self.sync_worlds.insert(world.clone());
+            },
+            SyncRequest::Unsubscribe => {
+                self.sync_worlds.remove(&world);
+            },
+            _ => {},
+        }
+        let sync = SyncCommand{client_id: self.sync_client, request};
+        ctx.spawn(self.manager.send(RouteSync{world: Some(world), sync}).into_actor(self).map(|res, _, ctx| {
+            match res {
+                Ok(Ok(reply)) => ctx.text(reply),
+                Ok(Err(e)) => ctx.text(format!("error: {}", e)),
+                Err(_) => ctx.text("error: world unavailable"),
+            }
+        }));
     }
 
     /// helper method that sends ping to client every second.
     ///
     /// also this method checks heartbeats from client
     fn hb(&self, ctx: &mut <Self as Actor>::Context) {
-        ctx.run_interval(HEARTBEAT_INTERVAL, |act, ctx| {
+        ctx.run_interval(self.hb_interval, |act, ctx| {
             // check client heartbeats
-            if Instant::now().duration_since(act.hb) > CLIENT_TIMEOUT {
+            if Instant::now().duration_since(act.hb) > act.client_timeout {
                 // heartbeat timed out
                 println!("Websocket Client heartbeat failed, disconnecting!");
 
@@ -104,12 +229,112 @@ impl MyWebSocket {
     }
 }
 
+fn print_usage() {
+    println!("usage: hello-actix [--config <file>] [--<setting>=<value>...] [command]");
+    println!("  (none)                      run the world server");
+    println!("  headless [ticks]            run ticks as fast as possible and report timings");
+    println!("  bench astar [searches]      time A* between random road tiles of sub-map.bin");
+    println!("  bench jps [searches]        time Jump Point Search against A* on the same tiles");
+    println!("  bench tick [ticks] [axies]  time full updates with extra axies");
+    println!("  generate <file> [width] [height] [seed] [alley density] [resource density]");
+    println!("                              write a procedural map, map_width x map_height by default");
+}
+
+fn generate_map(config: &ServerConfig, args: &Vec<String>, path: &str) {
+    let mut params = MapGenParams::new(
+        arg_or(args, 2, config.map_width),
+        arg_or(args, 3, config.map_height),
+        arg_or(args, 4, 0));
+    params.alley_density = arg_or(args, 5, params.alley_density);
+    params.resource_density = arg_or(args, 6, params.resource_density);
+    let result = mapgen::generate(&params).and_then(|tm| tm.save_map_to(path).map_err(|e| e.to_string()));
+    match result {
+        Ok(_) => println!("{}x{} map with seed {} written to {}", params.width, params.height, params.seed, path),
+        Err(e) => println!("error generating map: {}", e),
+    }
+}
+
+fn arg_or<T: std::str::FromStr>(args: &Vec<String>, index: usize, default: T) -> T {
+    args.get(index).and_then(|v| v.parse::<T>().ok()).unwrap_or(default)
+}
+
 fn main() {
     let _ = tracing_subscriber::fmt::try_init();
+
+    let all_args: Vec<String> = std::env::args().skip(1).collect();
+    let (config, args) = match ServerConfig::load_settings(&all_args) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            println!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    // a generated map needs no map file
+    if args.get(0).map(|a| a.as_str()) != Some("generate") {
+        if let Err(e) = config.check_files() {
+            println!("{}", e);
+            std::process::exit(2);
+        }
+    }
+
+    match args.get(0).map(|a| a.as_str()) {
+        None => {},
+        Some("headless") => {
+            bench::run_headless(&config, arg_or(&args, 1, 1000));
+            return;
+        },
+        Some("bench") => {
+            match args.get(1).map(|a| a.as_str()) {
+                Some("astar") => bench::bench_astar(&config, arg_or(&args, 2, 1000)),
+                Some("jps") => bench::bench_jps(&config, arg_or(&args, 2, 1000)),
+                Some("tick") => bench::bench_tick(&config, arg_or(&args, 2, 100), arg_or(&args, 3, 5000)),
+                _ => print_usage(),
+            }
+            return;
+        },
+        Some("generate") => {
+            match args.get(1) {
+                Some(path) => generate_map(&config, &args, path),
+                None => print_usage(),
+            }
+            return;
+        },
+        Some(_) => {
+            print_usage();
+            return;
+        }
+    }
+
     // initialize system
-    let code = System::run(|| {
-        Arbiter::new().exec_fn(|| {
+    let code = System::run(move || {
+        Arbiter::new().exec_fn(move || {
             let io_actor = IOWorldActior::from_registry();
+            let manager = WorldManager::from_registry();
+            manager.do_send(CreateWorld{name: DEFAULT_WORLD.to_string(), config: config.clone()});
+            for (name, path) in config.worlds.iter() {
+                match ServerConfig::from_file(path).and_then(|c| c.validate().map(|_| c)) {
+                    Ok(world_config) => manager.do_send(CreateWorld{name: name.clone(), config: world_config}),
+                    Err(e) => println!("world {} not started: {}", name, e),
+                }
+            }
+
+            let http_bind = config.http_bind.clone();
+            let server = HttpServer::new(move || {
+                App::new()
+                    .data(manager.clone())
+                    .data(config.clone())
+                    .wrap(middleware::Logger::default())
+                    .configure(admin_api::config)
+                    .service(web::resource("/ws/").route(web::get().to(ws_index)))
+                    .service(web::resource("/ws/{world}/").route(web::get().to(ws_world_index)))
+            });
+            match server.bind(&http_bind) {
+                Ok(server) => {
+                    server.run();
+                    println!("Admin API listening on http://{}", http_bind);
+                },
+                Err(e) => println!("Admin API disabled, can not bind {}: {:?}", http_bind, e),
+            }
             thread::spawn(move || {
                 loop {
                     let mut cmd = String::new();
@@ -0,0 +1,361 @@
+//! Initial world population, declared in a TOML scenario file.
+//! Entities are either listed one by one or produced by `fill` rules
+//! that cover every matching tile of an area.
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+
+use legion::prelude::*;
+
+use crate::ecs::components::{*};
+use crate::ecs::submap::{TileMap};
+use crate::ecs::parcel::{LandParcels};
+use crate::ecs::building::{self, BuildingKind, Buildings};
+use crate::config::{ServerConfig};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScenarioError {
+    Io(String, String),
+    Parse(String),
+    Invalid(String),
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScenarioError::Io(path, e) => write!(f, "can not read {}: {}", path, e),
+            ScenarioError::Parse(e) => write!(f, "invalid scenario file: {}", e),
+            ScenarioError::Invalid(reason) => write!(f, "invalid scenario: {}", reason),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Area {
+    pub x: i32,
+    pub y: i32,
+    pub w: i32,
+    pub h: i32,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum GoalSpec {
+    /// Gather at (x, y) and bring it back home
+    Gather { x: i32, y: i32 },
+}
+
+fn default_owner() -> u32 {
+    1
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AxieSpec {
+    pub x: i32,
+    pub y: i32,
+    #[serde(default = "default_owner")]
+    pub owner: u32,
+    /// Defaults to the spawn position
+    pub home: Option<(i32, i32)>,
+    #[serde(default = "default_true")]
+    pub auto_collect: bool,
+    pub goal: Option<GoalSpec>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ResourceNodeSpec {
+    pub x: i32,
+    pub y: i32,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChimeraSpawnerSpec {
+    pub x: i32,
+    pub y: i32,
+    /// Chimeras appear on a random reachable tile of the area, at (x, y) when not set
+    pub area: Option<Area>,
+    #[serde(default)]
+    pub count: i32,
+    pub cooldown_ms: i32,
+}
+
+/// Gives the land parcel containing (x, y) to `owner`
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ParcelSpec {
+    pub x: i32,
+    pub y: i32,
+    pub owner: u32,
+}
+
+/// A finished building, its footprint starts at (x, y) on a parcel of `owner`
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BuildingSpec {
+    pub kind: BuildingKind,
+    pub x: i32,
+    pub y: i32,
+    #[serde(default = "default_owner")]
+    pub owner: u32,
+    /// Resources a storage starts with
+    #[serde(default)]
+    pub stored: u32,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FillModel {
+    /// An axie on every land tile
+    Axie,
+    /// A resource node on every resource tile
+    Resource,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FillRule {
+    pub model: FillModel,
+    pub area: Area,
+    #[serde(default = "default_owner")]
+    pub owner: u32,
+    #[serde(default = "default_true")]
+    pub auto_collect: bool,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scenario {
+    pub axies: Vec<AxieSpec>,
+    pub resource_nodes: Vec<ResourceNodeSpec>,
+    pub chimera_spawners: Vec<ChimeraSpawnerSpec>,
+    pub parcels: Vec<ParcelSpec>,
+    pub buildings: Vec<BuildingSpec>,
+    pub fill: Vec<FillRule>,
+}
+
+/// What `Scenario::populate` inserted
+#[derive(Clone, Debug, Default)]
+pub struct PopulateReport {
+    pub axies: usize,
+    pub resource_nodes: usize,
+    pub chimera_spawners: usize,
+}
+
+fn in_map(tm: &TileMap, x: i32, y: i32) -> bool {
+    x >= 1 && y >= 1 && x < tm.w as i32 - 1 && y < tm.h as i32 - 1
+}
+
+impl Scenario {
+    pub fn from_file(path: &str) -> Result<Self, ScenarioError> {
+        let text = fs::read_to_string(path).map_err(|e| ScenarioError::Io(path.to_string(), e.to_string()))?;
+        toml::from_str(&text).map_err(|e| ScenarioError::Parse(e.to_string()))
+    }
+
+    /// The scenario used without a scenario file: axies on the land tiles
+    /// and resource nodes on the resource tiles of the configured spawn area
+    pub fn from_spawn_area(config: &ServerConfig) -> Self {
+        let area = Area {
+            x: config.spawn_min_x,
+            y: config.spawn_min_y,
+            w: config.spawn_max_x - config.spawn_min_x,
+            h: config.spawn_max_y - config.spawn_min_y,
+        };
+        Scenario {
+            fill: vec![
+                FillRule { model: FillModel::Axie, area, owner: 1, auto_collect: true },
+                FillRule { model: FillModel::Resource, area, owner: 1, auto_collect: true },
+            ],
+            ..Scenario::default()
+        }
+    }
+
+    /// The scenario file of the config, or the spawn area one when there is none
+    pub fn load(config: &ServerConfig) -> Result<Self, ScenarioError> {
+        match &config.scenario_path {
+            Some(path) => Scenario::from_file(path),
+            None => Ok(Scenario::from_spawn_area(config)),
+        }
+    }
+
+    /// Checks every explicit position against the map
+    pub fn validate(&self, tm: &TileMap) -> Result<(), ScenarioError> {
+        let invalid = |what: &str, x: i32, y: i32, reason: &str| {
+            Err(ScenarioError::Invalid(format!("{} at {},{} {}", what, x, y, reason)))
+        };
+        for axie in self.axies.iter() {
+            if !in_map(tm, axie.x, axie.y) {
+                return invalid("axie", axie.x, axie.y, "is outside the map");
+            }
+            if !tm.can_move_to(&(axie.x, axie.y)) {
+                return invalid("axie", axie.x, axie.y, "is not reachable");
+            }
+            if let Some((hx, hy)) = axie.home {
+                if !in_map(tm, hx, hy) {
+                    return invalid("axie home", hx, hy, "is outside the map");
+                }
+            }
+            if let Some(GoalSpec::Gather { x, y }) = axie.goal {
+                if !in_map(tm, x, y) {
+                    return invalid("axie goal", x, y, "is outside the map");
+                }
+            }
+        }
+        for node in self.resource_nodes.iter() {
+            if !in_map(tm, node.x, node.y) {
+                return invalid("resource node", node.x, node.y, "is outside the map");
+            }
+        }
+        for spawner in self.chimera_spawners.iter() {
+            if !in_map(tm, spawner.x, spawner.y) {
+                return invalid("chimera spawner", spawner.x, spawner.y, "is outside the map");
+            }
+            if spawner.cooldown_ms <= 0 {
+                return invalid("chimera spawner", spawner.x, spawner.y, "needs a positive cooldown_ms");
+            }
+            if let Some(area) = spawner.area {
+                if area.w <= 0 || area.h <= 0 {
+                    return invalid("chimera spawner", spawner.x, spawner.y, "has an empty area");
+                }
+            }
+        }
+        for parcel in self.parcels.iter() {
+            if !in_map(tm, parcel.x, parcel.y) {
+                return invalid("parcel", parcel.x, parcel.y, "is outside the map");
+            }
+            if !tm.is_land_tile(&(parcel.x, parcel.y)) {
+                return invalid("parcel", parcel.x, parcel.y, "is not a land tile");
+            }
+        }
+        for building in self.buildings.iter() {
+            if !in_map(tm, building.x, building.y) {
+                return invalid("building", building.x, building.y, "is outside the map");
+            }
+        }
+        for rule in self.fill.iter() {
+            if rule.area.w < 0 || rule.area.h < 0 {
+                return invalid("fill area", rule.area.x, rule.area.y, "has a negative size");
+            }
+        }
+        Ok(())
+    }
+
+    /// Gives the listed parcels to their owners, returns how many
+    pub fn claim_parcels(&self, parcels: &mut LandParcels) -> Result<usize, ScenarioError> {
+        for spec in self.parcels.iter() {
+            let id = parcels.at(&(spec.x, spec.y)).map(|p| p.id)
+                .ok_or_else(|| ScenarioError::Invalid(format!("parcel at {},{} is not a land tile", spec.x, spec.y)))?;
+            parcels.set_owner(id, Some(spec.owner)).map_err(ScenarioError::Invalid)?;
+        }
+        Ok(self.parcels.len())
+    }
+
+    /// Adds the finished buildings, after the parcels are claimed. Returns how many.
+    pub fn place_buildings(&self, world: &mut World, tm: &TileMap, parcels: &LandParcels, buildings: &mut Buildings) -> Result<usize, ScenarioError> {
+        let mut count = 0;
+        for spec in self.buildings.iter() {
+            let id = buildings.place_built(tm, parcels, spec.kind, spec.owner, spec.x, spec.y, spec.stored)
+                .map_err(|e| ScenarioError::Invalid(format!("building at {},{}: {}", spec.x, spec.y, e)))?;
+            if let Some(building) = buildings.get(id) {
+                building::insert_entity(world, building);
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Inserts the scenario entities into an empty world
+    pub fn populate(&self, world: &mut World, tm: &TileMap) -> Result<PopulateReport, ScenarioError> {
+        self.validate(tm)?;
+        let mut report = PopulateReport::default();
+
+        let mut axies = Vec::<AxieSpec>::new();
+        let mut resource_nodes = Vec::<(LandPos,)>::new();
+        for rule in self.fill.iter() {
+            for y in rule.area.y.max(1)..(rule.area.y + rule.area.h).min(tm.h as i32 - 1) {
+                for x in rule.area.x.max(1)..(rule.area.x + rule.area.w).min(tm.w as i32 - 1) {
+                    match rule.model {
+                        FillModel::Axie => {
+                            if tm.is_land_tile(&(x, y)) {
+                                axies.push(AxieSpec{x, y, owner: rule.owner, home: None, auto_collect: rule.auto_collect, goal: None});
+                            }
+                        },
+                        FillModel::Resource => {
+                            if tm.is_resource_tile(&(x, y)) {
+                                resource_nodes.push((LandPos(x, y),));
+                            }
+                        },
+                    }
+                }
+            }
+        }
+        axies.extend(self.axies.iter().cloned());
+        resource_nodes.extend(self.resource_nodes.iter().map(|n| (LandPos(n.x, n.y),)));
+
+        //Init static building
+        if resource_nodes.len() > 0 {
+            report.resource_nodes = resource_nodes.len();
+            world.insert(
+                (Model(BuildingModelType::ResourceNode as u32), Static,),
+                resource_nodes
+            );
+        }
+
+        for spawner in self.chimera_spawners.iter() {
+            let (ax, ay, aw, ah) = match spawner.area {
+                Some(a) => (a.x, a.y, a.w, a.h),
+                None => (spawner.x, spawner.y, 0, 0),
+            };
+            world.insert(
+                (),
+                vec![
+                    (LandPos(spawner.x, spawner.y), ChimeraSpawner{x: ax, y: ay, w: aw, h: ah, count: spawner.count, cooldown_ms: spawner.cooldown_ms, tick_ms: 0}),
+                ],
+            );
+            report.chimera_spawners += 1;
+        }
+
+        // axies without a goal are inserted in batches sharing their tags
+        let mut batches = HashMap::<(u32, bool), Vec<(LandPos, HomeLand)>>::new();
+        for axie in axies.iter() {
+            let land_pos = LandPos(axie.x, axie.y);
+            let home = match axie.home {
+                Some((hx, hy)) => HomeLand(LandPos(hx, hy)),
+                None => HomeLand(land_pos),
+            };
+            report.axies += 1;
+            match axie.goal {
+                Some(GoalSpec::Gather { x, y }) => {
+                    let entity = world.insert(
+                        (Owner(axie.owner), Model(UnitModelType::Axie as u32), GGoal),
+                        vec![(land_pos, home, GatherResourceGoal{step: 0, home_pos: home.0, target_pos: LandPos(x, y)})]
+                    )[0];
+                    if axie.auto_collect {
+                        let _ = world.add_tag(entity, AutoCollect);
+                    }
+                },
+                None => {
+                    batches.entry((axie.owner, axie.auto_collect)).or_insert_with(Vec::new).push((land_pos, home));
+                }
+            }
+        }
+        for ((owner, auto_collect), batch) in batches.into_iter() {
+            if auto_collect {
+                world.insert((Owner(owner), Model(UnitModelType::Axie as u32), AutoCollect), batch);
+            } else {
+                world.insert((Owner(owner), Model(UnitModelType::Axie as u32)), batch);
+            }
+        }
+
+        Ok(report)
+    }
+}
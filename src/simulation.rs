@@ -0,0 +1,475 @@
+use std::io;
+use std::time::{Duration, Instant};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use legion::prelude::*;
+
+use super::*;
+use crate::ecs::components::{*};
+
+use crate::ecs::submap::{TileMap};
+use crate::ecs::types::{*};
+use crate::ecs::systems;
+use crate::ecs::sync::{self, ClientSyncResource};
+use crate::ecs::admin;
+use crate::ecs::metrics::{MetricsResource, SimMetrics};
+use crate::ecs::flow_field::{FlowFieldResource, FlowFields};
+use crate::ecs::connectivity::{Connectivity, ConnectivityResource};
+use crate::ecs::parcel::{LandParcelResource, LandParcels};
+use crate::ecs::building::{Buildings, BuildingsResource};
+use crate::config::{ServerConfig};
+use crate::scenario::{Scenario};
+
+/// World, resources and schedule of one lunacia world,
+/// advanced one fixed step at a time by whoever owns it.
+/// Events pile up until `drain_events` is called.
+pub struct Simulation {
+    pub universe: Universe,
+    pub world: World,
+    pub resources: Resources,
+    schedule: Schedule,
+    inputs: Vec<PlayerInputRequest>,
+}
+
+#[derive(Clone, Debug)]
+pub struct HeadlessReport {
+    pub ticks: u32,
+    pub total: Duration,
+    pub min: Duration,
+    pub max: Duration,
+}
+
+impl HeadlessReport {
+    pub fn mean(&self) -> Duration {
+        if self.ticks == 0 {
+            return Duration::from_millis(0);
+        }
+        self.total / self.ticks
+    }
+}
+
+impl Simulation {
+    pub fn new(config: &ServerConfig) -> io::Result<Self> {
+        let mut resources = Resources::default();
+        let mut tile_map = TileMap::new(config.map_width, config.map_height);
+        tile_map.topology = config.movement_topology();
+
+        if let Err(e) = tile_map.load_map_from(&config.map_path) {
+            println!("error parsing header: {:?}", e);
+            return Err(e);
+        }
+        let scenario = Scenario::load(config)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+
+        resources.insert(config.game_config());
+        resources.insert(EmitEventResource(Vec::<(i32, LunaciaWorldEvent)>::new()));
+        resources.insert(QuadrantDataHashMapResource(HashMap::new()));
+        resources.insert(PathwayHashMapResource(HashMap::new()));
+        resources.insert(FlowFieldResource(FlowFields::new(config.flow_field_demand, config.max_flow_fields)));
+        resources.insert(ClientSyncResource::default());
+        resources.insert(MetricsResource(Arc::new(SimMetrics::new())));
+
+        let universe = Universe::new();
+        let mut world = universe.create_world();
+
+        let report = scenario.populate(&mut world, &tile_map)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        let mut parcels = LandParcels::build(&tile_map);
+        let claimed = scenario.claim_parcels(&mut parcels)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        println!("Total land parcels: {:?}, {:?} owned", parcels.len(), claimed);
+        let mut buildings = Buildings::new();
+        let placed = scenario.place_buildings(&mut world, &tile_map, &parcels, &mut buildings)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        if placed > 0 {
+            println!("Total buildings: {:?}", placed);
+        }
+        println!("Total ressource nodes: {:?}", report.resource_nodes);
+        println!("Total axie: {:?}", report.axies);
+        if report.chimera_spawners > 0 {
+            println!("Total chimera spawners: {:?}", report.chimera_spawners);
+        }
+        resources.insert(ConnectivityResource(Connectivity::build(&tile_map)));
+        resources.insert(LandParcelResource(parcels));
+        resources.insert(BuildingsResource(buildings));
+        resources.insert(TileMapResource(tile_map));
+
+        Ok(Simulation {
+            universe: universe,
+            world: world,
+            resources: resources,
+            schedule: build_schedule(),
+            inputs: Vec::new(),
+        })
+    }
+
+    pub fn submit(&mut self, input: PlayerInputRequest) {
+        self.inputs.push(input);
+    }
+
+    pub fn frame(&self) -> u32 {
+        self.resources.get::<GameConfigResource>().map_or(0, |conf| conf.number_of_updates)
+    }
+
+    pub fn metrics(&self) -> Option<Arc<SimMetrics>> {
+        self.resources.get::<MetricsResource>().map(|m| m.0.clone())
+    }
+
+    /// Moves queued player inputs into the world and runs the schedule once
+    pub fn step(&mut self) -> Duration {
+        let world = &mut self.world;
+        if self.inputs.len() > 0 {
+            let mut input_axies = Vec::new();
+            let mut input_get_states = Vec::new();
+            for input in &self.inputs {
+                match input {
+                    PlayerInputRequest::GatherResource{request_id, owner, axie_index} => {
+                        input_axies.push((PlayerInput{request_id: *request_id, owner: *owner, status: 0}, PlayerInputAxie{axie_index: *axie_index}, PlayerInputAxieGatherResource{resource_id: 1}))
+                    },
+                    PlayerInputRequest::GetPlayerState{request_id, owner, tx, ty} => {
+                        input_get_states.push((PlayerInput{request_id: *request_id, owner: *owner, status: 0}, PlayerInputGetStateAroundLand(*tx, *ty)))
+                    },
+                    _ => {}
+                }
+            }
+            self.inputs.clear();
+            if input_axies.len() > 0 {
+                world.insert(
+                    (),
+                    input_axies
+                );
+            }
+            if input_get_states.len() > 0 {
+                world.insert(
+                    (),
+                    input_get_states
+                );
+            }
+        }
+
+        let events_before = self.pending_events();
+        let tick_start = Instant::now();
+        self.schedule.execute(world, &mut self.resources);
+        let elapsed = tick_start.elapsed();
+        let new_events = self.pending_events().saturating_sub(events_before);
+        if let Some(metrics) = self.resources.get::<MetricsResource>() {
+            metrics.0.observe_tick(elapsed);
+            metrics.0.add_events(new_events);
+        }
+        elapsed
+    }
+
+    fn pending_events(&self) -> usize {
+        self.resources.get::<EmitEventResource>().map_or(0, |p| p.0.len())
+    }
+
+    /// Takes the events emitted since the last drain, keyed by chunk
+    pub fn drain_events(&mut self) -> Vec<(i32, LunaciaWorldEvent)> {
+        match self.resources.get_mut::<EmitEventResource>() {
+            Some(mut p) => std::mem::replace(&mut p.0, Vec::new()),
+            None => Vec::new(),
+        }
+    }
+
+    /// Runs `ticks` updates back to back, as fast as possible
+    pub fn run_ticks(&mut self, ticks: u32) -> HeadlessReport {
+        let mut report = HeadlessReport {
+            ticks: ticks,
+            total: Duration::from_millis(0),
+            min: Duration::from_secs(u64::MAX),
+            max: Duration::from_millis(0),
+        };
+        for _ in 0..ticks {
+            let elapsed = self.step();
+            self.drain_events();
+            report.total += elapsed;
+            report.min = report.min.min(elapsed);
+            report.max = report.max.max(elapsed);
+        }
+        if ticks == 0 {
+            report.min = Duration::from_millis(0);
+        }
+        report
+    }
+}
+
+pub fn build_schedule() -> Schedule {
+    let update_chimera_spawners = systems::build_update_chimera_spawners();
+    let update_positions = systems::build_update_moving();
+    let update_follow_paths = systems::build_update_follow_paths();
+    let update_new_pos = systems::build_update_new_pos();
+
+    // update positions using a system
+    let set_quadrant_data_hash_map = systems::build_set_quadrant_data_hash_map();
+
+    let thread_local_example = Box::new(|world: &mut World, _resources: &mut Resources| {
+        let mut tmp_focusing_pos = LandPos(0, 0);
+        if let Some(conf) = &_resources.get::<GameConfigResource>() {
+            tmp_focusing_pos = LandPos(conf.tmp_focusing_pos.0, conf.tmp_focusing_pos.1);
+        }
+        // if let Some(p) = &mut _resources.get_mut::<PlayerInputResource>() {
+        //     let ins = &mut p.0;
+        //     //TODO quick verify valid input?
+        //     while let Some(inp) = &self.inputs.pop_front() {
+        //         //ins.push_back(*inp);
+        //     }
+        //     // let act = IOWorldActior::from_registry();
+        //     // act.do_send(WorldPong);
+        // }
+
+        
+
+        if let Some(p) = &mut _resources.get_mut::<QuadrantDataHashMapResource>() {
+            let hm = &mut p.0;
+            {
+                let query = <(Write<PlayerInput>, Read<PlayerInputGetStateAroundLand>)>::query();
+                for (mut pi, lp) in query.iter_mut(world) {
+                    match &pi.status {
+                        0 => {
+                            tmp_focusing_pos = LandPos(lp.0, lp.1);
+                            let visible_chunk_keys = tmp_focusing_pos.get_hash_map_key_successors(1);
+                            let mut total = 0;
+                            for chunk_key in visible_chunk_keys.iter() {
+                                //println!("chunk_key {:?}", chunk_key);
+                                match hm.get(chunk_key) {
+                                    Some(chunk) => {
+                                        
+                                        for objs in chunk.values() {
+                                            for (entity, qd) in objs.iter() {
+                                                total += 1;
+                                                //println!("State: {:?} - {:?},{:?}", entity.index(), qd.land_pos.0, qd.land_pos.1);
+                                            }
+                                        }
+                                    },
+                                    None => {}
+                                }
+                            }
+                            println!("Focus at {:?},{:?} total entities: {:?}", lp.0, lp.1, total);
+                            pi.status += 1;
+                        },
+                        _ => ()
+                    }
+                }
+            }
+
+            {
+                let mut input_hm : HashMap <(u32, u32), PlayerInputAxieGatherResource> = HashMap::new();
+
+                let query = <(Write<PlayerInput>, Read<PlayerInputAxie>, Read<PlayerInputAxieGatherResource>)>::query();
+                for (mut pi, ax, gr) in query.iter_mut(world) {
+                    match &pi.status {
+                        0 => {
+                            pi.status += 1;
+                            input_hm.insert((pi.owner, ax.axie_index), *gr);
+                        },
+                        _ => ()
+                    }
+                }
+
+                for (key, val) in input_hm.iter() {
+                    let owner = Owner(key.0);
+                    let axie_query = <(Read<LandPos>)>::query()
+                        .filter(tag_value(&owner) & tag_value(&Model(UnitModelType::Axie as u32)));
+                    let mut axie_found : Option<Entity> = None;
+                    for (mut axie_entity, (axie_pos)) in axie_query.iter_entities_mut(world) {
+                        if axie_entity.index() == key.1 {
+                            axie_found = Some(axie_entity);
+                        }
+                    }
+                    match axie_found {
+                        Some(entity) => {
+                            // if world.get_component::<GGoal>(entity) == None {
+                            //     world.add_component(entity, GatherResourceGoal{
+                            //         step:0, 
+                            //         home_pos:LandPos(5, 5), 
+                            //         target_pos:LandPos(21, 21)}
+                            //     );
+                            // } else{
+                            //     println!("Axie busing");
+                            // }
+                            
+                        },
+                        None => {
+                            println!("Invalid axie input");
+                        }
+                    }
+                    //println!("key: {} val: {}", key, val);
+                }
+            }
+            
+            //hm.clear();
+        }
+
+        sync::update_client_sync(world, _resources);
+
+        let entity_counts = admin::world_stats(world, _resources).entities_per_model;
+        if let Some(metrics) = _resources.get::<MetricsResource>() {
+            metrics.0.set_entity_counts(entity_counts);
+        }
+
+        if let Some(conf) = &mut _resources.get_mut::<GameConfigResource>() {
+            conf.number_of_updates += 1;
+            conf.tmp_focusing_pos = (tmp_focusing_pos.0, tmp_focusing_pos.1);
+        }
+    });
+
+    Schedule::builder()
+        .add_system(set_quadrant_data_hash_map)
+        //.add_system(update_chimeras_as_boid)
+        .add_system(update_follow_paths)
+        .add_system(update_positions)
+        .add_system(update_chimera_spawners)
+        .add_system(update_new_pos)
+
+        .add_system(systems::build_gather_resource_goals())
+        .add_system(systems::build_gather_resource_actions())
+        .add_system(systems::build_release_resource_actions())
+        .add_system(systems::build_update_constructions())
+        .add_system(systems::build_path_failures())
+
+        //.add_system(systems::build_player_input_axie_gather_resource())
+
+        .add_system(systems::build_player_input_cleans())
+
+        .add_system(systems::build_auto_collect_resources())
+        
+        // This flushes all command buffers of all systems.
+        .flush()
+        // a thread local system or function will wait for all previous systems to finish running,
+        // and then take exclusive access of the world.
+        .add_thread_local_fn(thread_local_example)
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Simulation on the map of `rows`, with no scenario
+    fn simulation_on(config: &ServerConfig, rows: &[&str]) -> Simulation {
+        let mut tile_map = TileMap::from_rows(rows);
+        tile_map.topology = config.movement_topology();
+        let mut resources = Resources::default();
+        resources.insert(config.game_config());
+        resources.insert(EmitEventResource(Vec::<(i32, LunaciaWorldEvent)>::new()));
+        resources.insert(QuadrantDataHashMapResource(HashMap::new()));
+        resources.insert(PathwayHashMapResource(HashMap::new()));
+        resources.insert(FlowFieldResource(FlowFields::new(config.flow_field_demand, config.max_flow_fields)));
+        resources.insert(ClientSyncResource::default());
+        resources.insert(MetricsResource(Arc::new(SimMetrics::new())));
+        resources.insert(ConnectivityResource(Connectivity::build(&tile_map)));
+        resources.insert(LandParcelResource(LandParcels::build(&tile_map)));
+        resources.insert(BuildingsResource(Buildings::new()));
+        resources.insert(TileMapResource(tile_map));
+        let universe = Universe::new();
+        let world = universe.create_world();
+        Simulation { universe, world, resources, schedule: build_schedule(), inputs: Vec::new() }
+    }
+
+    /// Adds a unit at `from` heading to `to`
+    fn send_unit(sim: &mut Simulation, from: (i32, i32), to: (i32, i32)) -> Entity {
+        sim.world.insert((), vec![
+            (LandPos(from.0, from.1), FollowPath::new(from.0, from.1, to.0, to.1), Moving::new()),
+        ])[0]
+    }
+
+    fn stored_at(sim: &Simulation, pos: &(i32, i32)) -> Option<u32> {
+        sim.resources.get::<BuildingsResource>().and_then(|b| b.0.at(pos).map(|b| b.stored))
+    }
+
+    /// The axie of owner 2 gathers next to its home and brings the resource
+    /// to the storage standing on it
+    #[test]
+    fn example_scenario_fills_the_storage() {
+        let mut config = ServerConfig::default();
+        config.scenario_path = Some("scenarios/example.toml".to_string());
+        let mut sim = Simulation::new(&config).unwrap();
+        let before = stored_at(&sim, &(60, 60)).unwrap();
+        let mut frames = 0;
+        while stored_at(&sim, &(60, 60)) == Some(before) && frames < 3000 {
+            sim.step();
+            sim.drain_events();
+            frames += 1;
+        }
+        assert!(stored_at(&sim, &(60, 60)).unwrap() > before, "nothing stored after {} frames", frames);
+    }
+
+    /// Every segment announced along a path with diagonal steps and cost changes
+    /// ends with the relocation it promised, on the frame it promised
+    #[test]
+    fn move_segments_arrive_on_their_frame() {
+        let mut config = ServerConfig::default();
+        config.movement_directions = 8;
+        let mut sim = simulation_on(&config, &[
+            "..........",
+            ".RRRRllll.",
+            ".llllllll.",
+            ".llllllll.",
+            ".llllllll.",
+            "..........",
+        ]);
+        let unit = send_unit(&mut sim, (1, 1), (8, 4));
+        let mut events = Vec::new();
+        while sim.world.get_component::<FollowPath>(unit).is_some() && sim.frame() < 500 {
+            sim.step();
+            events.extend(sim.drain_events().into_iter().map(|(_, event)| event));
+        }
+        assert!(sim.world.get_component::<FollowPath>(unit).is_none(), "still walking at frame {}", sim.frame());
+
+        let relocations: Vec<(u32, (i32, i32))> = events.iter().filter_map(|event| match event {
+            LunaciaWorldEvent::EventRelocation{frame, id, tx, ty} if *id == unit.index() => Some((*frame, (*tx, *ty))),
+            _ => None,
+        }).collect();
+        let mut segments = 0;
+        let mut diagonal = false;
+        let mut last_end = None;
+        for event in events.iter() {
+            if let LunaciaWorldEvent::EventMoveSegment{frame, id, tx, ty, vx, vy, arrive_frame, ..} = event {
+                assert_eq!(*id, unit.index());
+                let arrival = relocations.iter().find(|(f, pos)| f > frame && *pos == (*tx, *ty));
+                assert_eq!(arrival.map(|(f, _)| *f), Some(*arrive_frame), "segment to {},{} from frame {}", tx, ty, frame);
+                segments += 1;
+                diagonal |= *vx != 0 && *vy != 0;
+                last_end = Some((*tx, *ty));
+            }
+        }
+        assert!(segments > 1 && diagonal);
+        // it stops next to its target, where the last segment ends
+        assert_eq!(last_end, relocations.last().map(|(_, pos)| *pos));
+        let pos = *sim.world.get_component::<LandPos>(unit).unwrap();
+        assert!(last_end == Some((pos.0, pos.1)));
+    }
+
+    /// Two retries 5 then 10 frames apart, then the unit walks as close as it can
+    #[test]
+    fn unreachable_target_is_retried_then_given_up() {
+        let config = ServerConfig::default();
+        assert_eq!((config.path_retries, config.path_retry_ms, config.fixed_time_ms), (2, 1000, 200));
+        let mut sim = simulation_on(&config, &[
+            "..........",
+            ".RRRR..RR.",
+            "..........",
+        ]);
+        let unit = send_unit(&mut sim, (1, 1), (8, 1));
+        let mut failed = Vec::new();
+        let mut attempts = Vec::new();
+        while sim.frame() < 20 {
+            sim.step();
+            for (_, event) in sim.drain_events() {
+                if let LunaciaWorldEvent::EventPathFailed{frame, fallback, ..} = event {
+                    failed.push((frame, fallback));
+                }
+            }
+            let fp = *sim.world.get_component::<FollowPath>(unit).unwrap();
+            attempts.push((fp.attempts, fp.retry_frame));
+        }
+        assert_eq!(attempts[0], (1, 5));
+        assert_eq!(attempts[5], (2, 15));
+        assert_eq!(attempts[14], (2, 15));
+        assert_eq!(failed, vec![(15, Some((4, 1)))]);
+        // a new target, with no retries spent
+        let fp = *sim.world.get_component::<FollowPath>(unit).unwrap();
+        assert_eq!(((fp.tx, fp.ty), fp.attempts), ((4, 1), 0));
+    }
+}
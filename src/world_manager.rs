@@ -0,0 +1,259 @@
+//! Named world instances hosted by one server. Every world is its own
+//! `LunaciaWorldActor` with its own map, config and schedule; commands and
+//! sessions pick one by name and fall back to `DEFAULT_WORLD`.
+
+use actix::prelude::*;
+use std::collections::HashMap;
+
+use lunacia::config::{ServerConfig};
+use crate::commands::{AdminCommand, CommandError};
+use crate::lunacia_world::{LunaciaWorldActor, StartWorld, StopWorld, SyncCommand};
+
+pub const DEFAULT_WORLD: &str = "main";
+
+/// Builds a world from `config` under `name`
+#[derive(Message)]
+#[rtype(result = "Result<(), String>")]
+pub struct CreateWorld {
+    pub name: String,
+    pub config: ServerConfig,
+}
+
+/// Stops a world and forgets its name
+#[derive(Message)]
+#[rtype(result = "Result<(), String>")]
+pub struct RemoveWorld {
+    pub name: String,
+}
+
+/// Address of a world, the default one when `name` is None
+#[derive(Message)]
+#[rtype(result = "Option<Addr<LunaciaWorldActor>>")]
+pub struct GetWorld {
+    pub name: Option<String>,
+}
+
+#[derive(Message)]
+#[rtype(result = "Vec<String>")]
+pub struct ListWorlds;
+
+/// Runs a command on a world, the default one when `world` is None
+pub struct RouteCommand {
+    pub world: Option<String>,
+    pub cmd: AdminCommand,
+}
+
+impl Message for RouteCommand {
+    type Result = Result<String, CommandError>;
+}
+
+/// Passes a sync request to a world, the default one when `world` is None
+pub struct RouteSync {
+    pub world: Option<String>,
+    pub sync: SyncCommand,
+}
+
+impl Message for RouteSync {
+    type Result = Result<String, CommandError>;
+}
+
+/// Splits a leading `@name` off a command line
+pub fn split_world_prefix(line: &str) -> (Option<String>, &str) {
+    let line = line.trim_start();
+    if line.starts_with('@') {
+        let end = line.find(char::is_whitespace).unwrap_or(line.len());
+        (Some(line[1..end].to_string()), &line[end..])
+    } else {
+        (None, line)
+    }
+}
+
+fn valid_name(name: &str) -> bool {
+    name.len() > 0 && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[derive(Default)]
+pub struct WorldManager {
+    worlds: HashMap<String, Addr<LunaciaWorldActor>>,
+}
+
+impl Actor for WorldManager {
+    type Context = Context<Self>;
+}
+impl actix::Supervised for WorldManager {}
+
+impl ArbiterService for WorldManager {
+    fn service_started(&mut self, _: &mut Context<Self>) {
+        println!("WorldManager Service started");
+    }
+}
+
+impl WorldManager {
+    fn world(&self, name: Option<&str>) -> Option<Addr<LunaciaWorldActor>> {
+        self.worlds.get(name.unwrap_or(DEFAULT_WORLD)).cloned()
+    }
+
+    fn check_new_name(&self, name: &str) -> Result<(), String> {
+        if !valid_name(name) {
+            return Err(format!("invalid world name '{}'", name));
+        }
+        if self.worlds.contains_key(name) {
+            return Err(format!("world {} already exists", name));
+        }
+        Ok(())
+    }
+
+    /// Starts a world and registers it under `name` once it has loaded, a
+    /// world that fails to load never gets a name
+    fn create_world(&mut self, name: &str, config: ServerConfig) -> ResponseActFuture<Self, (), String> {
+        if let Err(e) = self.check_new_name(name) {
+            return Box::new(fut::result(Err(e)));
+        }
+        let name = name.to_string();
+        let world = LunaciaWorldActor::new(&name).start();
+        Box::new(world.send(StartWorld{config: config}).into_actor(self).map(move |res, act, _| {
+            match res {
+                Ok(Ok(())) => {
+                    if let Err(e) = act.check_new_name(&name) {
+                        world.do_send(StopWorld);
+                        return Err(e);
+                    }
+                    act.worlds.insert(name, world);
+                    Ok(())
+                },
+                Ok(Err(e)) => Err(e),
+                Err(_) => Err(format!("world {} stopped while loading", name)),
+            }
+        }))
+    }
+
+    fn remove_world(&mut self, name: &str) -> Result<(), String> {
+        if name == DEFAULT_WORLD {
+            return Err(format!("world {} can not be stopped", DEFAULT_WORLD));
+        }
+        match self.worlds.remove(name) {
+            Some(world) => {
+                world.do_send(StopWorld);
+                Ok(())
+            },
+            None => Err(format!("no world {}", name)),
+        }
+    }
+
+    fn world_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.worlds.keys().cloned().collect();
+        names.sort();
+        names
+    }
+}
+
+impl Handler<CreateWorld> for WorldManager {
+    type Result = ResponseActFuture<Self, (), String>;
+
+    fn handle(&mut self, msg: CreateWorld, _: &mut Context<Self>) -> Self::Result {
+        self.create_world(&msg.name, msg.config)
+    }
+}
+
+impl Handler<RemoveWorld> for WorldManager {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: RemoveWorld, _: &mut Context<Self>) -> Self::Result {
+        self.remove_world(&msg.name)
+    }
+}
+
+impl Handler<GetWorld> for WorldManager {
+    type Result = Option<Addr<LunaciaWorldActor>>;
+
+    fn handle(&mut self, msg: GetWorld, _: &mut Context<Self>) -> Self::Result {
+        self.world(msg.name.as_ref().map(|n| n.as_str()))
+    }
+}
+
+impl Handler<ListWorlds> for WorldManager {
+    type Result = Vec<String>;
+
+    fn handle(&mut self, _: ListWorlds, _: &mut Context<Self>) -> Self::Result {
+        self.world_names()
+    }
+}
+
+impl Handler<RouteCommand> for WorldManager {
+    type Result = ResponseFuture<String, CommandError>;
+
+    fn handle(&mut self, msg: RouteCommand, _: &mut Context<Self>) -> Self::Result {
+        let name = msg.world.unwrap_or_else(|| DEFAULT_WORLD.to_string());
+        let world = self.world(Some(&name));
+        let cmd = msg.cmd;
+        Box::pin(async move {
+            match world {
+                Some(world) => match world.send(cmd).await {
+                    Ok(res) => res,
+                    Err(_) => Err(CommandError::Failed(format!("world {} unavailable", name))),
+                },
+                None => Err(CommandError::NotFound(format!("world {}", name))),
+            }
+        })
+    }
+}
+
+impl Handler<RouteSync> for WorldManager {
+    type Result = ResponseFuture<String, CommandError>;
+
+    fn handle(&mut self, msg: RouteSync, _: &mut Context<Self>) -> Self::Result {
+        let name = msg.world.unwrap_or_else(|| DEFAULT_WORLD.to_string());
+        let world = self.world(Some(&name));
+        let sync = msg.sync;
+        Box::pin(async move {
+            match world {
+                Some(world) => world.send(sync).await
+                    .map_err(|_| CommandError::Failed(format!("world {} unavailable", name))),
+                None => Err(CommandError::NotFound(format!("world {}", name))),
+            }
+        })
+    }
+}
+
+/// The commands about worlds themselves, see `Dispatch::Manager`
+impl Handler<AdminCommand> for WorldManager {
+    type Result = ResponseActFuture<Self, String, CommandError>;
+
+    fn handle(&mut self, cmd: AdminCommand, _: &mut Context<Self>) -> Self::Result {
+        if cmd.name == "create_world" {
+            return self.create_world_command(&cmd);
+        }
+        Box::new(fut::result(self.world_command(&cmd)))
+    }
+}
+
+impl WorldManager {
+    fn create_world_command(&mut self, cmd: &AdminCommand) -> ResponseActFuture<Self, String, CommandError> {
+        let args = &cmd.args;
+        let parsed = args.word("name").and_then(|name| args.word("config").map(|path| (name.to_string(), path.to_string())));
+        let (name, path) = match parsed {
+            Ok(parsed) => parsed,
+            Err(e) => return Box::new(fut::result(Err(e))),
+        };
+        let config = match ServerConfig::from_file(&path).and_then(|config| config.validate().map(|_| config)) {
+            Ok(config) => config,
+            Err(e) => return Box::new(fut::result(Err(CommandError::Failed(e.to_string())))),
+        };
+        Box::new(self.create_world(&name, config).map(move |res, _, _| {
+            res.map(|_| format!("World {} created from {}", name, path)).map_err(CommandError::Failed)
+        }))
+    }
+
+    fn world_command(&mut self, cmd: &AdminCommand) -> Result<String, CommandError> {
+        let args = &cmd.args;
+        match cmd.name {
+            "worlds" => Ok(self.world_names().join("\n")),
+            "stop_world" => {
+                let name = args.word("name")?;
+                self.remove_world(name).map_err(CommandError::Failed)?;
+                Ok(format!("World {} stopped", name))
+            },
+            _ => Err(CommandError::Unknown(cmd.name.to_string()))
+        }
+    }
+}